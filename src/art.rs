@@ -0,0 +1,289 @@
+use crate::hash::sha1;
+use crate::xdg;
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::process::Command;
+use tokio::sync::Notify;
+use tracing::{debug, error};
+
+/// How much downloaded art is kept, removing the art that was downloaded longest ago first
+const MAX_CACHE_SIZE: u64 = 50 * 1024 * 1024;
+
+/// How long art that could not be fetched is left alone before it is tried again
+const RETRY_FAILED_AFTER: Duration = Duration::from_secs(30 * 60);
+
+/// MusicBrainz asks to be told who is asking
+const USER_AGENT: &str = concat!(
+    "lizzy/",
+    env!("CARGO_PKG_VERSION"),
+    " ( https://github.com/stefur/lizzy )"
+);
+
+/// What goes on with art being fetched, shared with the tasks fetching it
+#[derive(Default)]
+struct Fetches {
+    /// Art that is being fetched, so that the same art is not fetched twice at once
+    fetching: Mutex<HashSet<String>>,
+    /// When art could not be fetched, so that it is not tried again for every change of the mediaplayer
+    failed: Mutex<HashMap<String, Instant>>,
+    /// Told whenever art has been downloaded, so that the output can show it
+    downloaded: Notify,
+}
+
+/// Resolves the art of tracks, fetching what is not in the cache yet in the background
+#[derive(Default)]
+pub struct Art(Arc<Fetches>);
+
+impl Art {
+    /// Resolve the art url of a track to a local path, or download remote art to the cache in the background
+    pub fn resolve(&self, url: &str) -> Option<String> {
+        if let Some(path) = url.strip_prefix("file://") {
+            return Some(percent_decode(path));
+        }
+
+        if url.starts_with("http://") || url.starts_with("https://") {
+            let path = cache_dir()?.join(hashed(url));
+            if path.exists() {
+                return Some(path.to_string_lossy().into_owned());
+            }
+            let (url, key) = (url.to_string(), url.to_string());
+            self.spawn_fetch(
+                key,
+                path.clone(),
+                async move { download(&url, &path).await },
+            );
+        }
+
+        None
+    }
+
+    /// Look up the cover of the release on MusicBrainz and the Cover Art Archive, for tracks without art of their own
+    pub fn lookup(&self, artist: &str, album: Option<&str>, title: &str) -> Option<String> {
+        let key = format!(
+            "musicbrainz:{}\0{}\0{}",
+            artist,
+            album.unwrap_or_default(),
+            title
+        );
+        let path = cache_dir()?.join(hashed(&key));
+        if path.exists() {
+            return Some(path.to_string_lossy().into_owned());
+        }
+
+        // The album finds the release best, otherwise the first release of the recording will do
+        let query = match album {
+            Some(album) => format!("release:{} AND artist:{}", phrase(album), phrase(artist)),
+            None => format!("recording:{} AND artist:{}", phrase(title), phrase(artist)),
+        };
+        let kind = if album.is_some() {
+            "release"
+        } else {
+            "recording"
+        };
+
+        self.spawn_fetch(key, path.clone(), async move {
+            let Some(release) = find_release(kind, &query).await else {
+                debug!("Found no release on MusicBrainz for {}", query);
+                return false;
+            };
+            let url = format!("https://coverartarchive.org/release/{}/front-250", release);
+            download(&url, &path).await
+        });
+
+        None
+    }
+
+    /// Wait until art has been downloaded since the last time
+    pub async fn downloaded(&self) {
+        self.0.downloaded.notified().await
+    }
+
+    /// Fetch art without holding up the output, which is refreshed once the art is there
+    fn spawn_fetch(
+        &self,
+        key: String,
+        path: PathBuf,
+        fetch: impl Future<Output = bool> + Send + 'static,
+    ) {
+        let fetches = self.0.clone();
+        if fetches
+            .failed
+            .lock()
+            .expect("Failed lock poisoned")
+            .get(&key)
+            .is_some_and(|failed| failed.elapsed() < RETRY_FAILED_AFTER)
+            || !fetches
+                .fetching
+                .lock()
+                .expect("Fetching lock poisoned")
+                .insert(key.clone())
+        {
+            return;
+        }
+
+        tokio::spawn(async move {
+            let fetched = fetch.await;
+            fetches
+                .fetching
+                .lock()
+                .expect("Fetching lock poisoned")
+                .remove(&key);
+
+            {
+                let mut failed = fetches.failed.lock().expect("Failed lock poisoned");
+                if !fetched {
+                    failed.insert(key, Instant::now());
+                    return;
+                }
+                failed.remove(&key);
+            }
+
+            debug!("Fetched art for {}", key);
+            if let Some(dir) = path.parent() {
+                prune(dir).await;
+            }
+            fetches.downloaded.notify_one();
+        });
+    }
+}
+
+/// The directory to keep downloaded art in, following the XDG base directories
+fn cache_dir() -> Option<PathBuf> {
+    Some(xdg::cache_home()?.join("lizzy").join("art"))
+}
+
+/// The url hashed into a file name, so the same art is only downloaded once, even across versions of lizzy
+fn hashed(url: &str) -> String {
+    sha1(url.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Quote a phrase for the search of MusicBrainz, which is Lucene syntax
+fn phrase(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Search MusicBrainz for a release or a recording, returning the id of the first release found
+async fn find_release(kind: &str, query: &str) -> Option<String> {
+    let output = Command::new("curl")
+        .args(["--silent", "--fail", "--max-time", "10", "--get"])
+        .args(["--user-agent", USER_AGENT])
+        .args(["--data-urlencode", &format!("query={}", query)])
+        .args(["--data", "limit=1", "--data", "fmt=json"])
+        .arg(format!("https://musicbrainz.org/ws/2/{}/", kind))
+        .output()
+        .await
+        .ok()
+        .filter(|output| output.status.success())?;
+
+    let found: serde_json::Value = serde_json::from_slice(&output.stdout).ok()?;
+    let release = match kind {
+        "release" => &found["releases"][0],
+        _ => &found["recordings"][0]["releases"][0],
+    };
+    release["id"].as_str().map(String::from)
+}
+
+/// Download art to the path, returning whether it is there
+async fn download(url: &str, path: &Path) -> bool {
+    if let Some(dir) = path.parent() {
+        if let Err(err) = tokio::fs::create_dir_all(dir).await {
+            error!("Failed to create art cache {}: {}", dir.display(), err);
+            return false;
+        }
+    }
+
+    // Download to a temporary file first so that a failed download never looks cached
+    let partial = path.with_extension("part");
+    let downloaded = Command::new("curl")
+        .args(["--silent", "--fail", "--location", "--max-time", "10"])
+        .args(["--user-agent", USER_AGENT])
+        .arg("--output")
+        .arg(&partial)
+        .arg(url)
+        .status()
+        .await
+        .is_ok_and(|status| status.success());
+
+    if !downloaded || tokio::fs::rename(&partial, path).await.is_err() {
+        error!("Failed to download art from {}", url);
+        let _ = tokio::fs::remove_file(&partial).await;
+        return false;
+    }
+
+    true
+}
+
+/// Remove the art that was downloaded longest ago until the cache fits in its size again
+async fn prune(dir: &Path) {
+    let Ok(mut entries) = tokio::fs::read_dir(dir).await else {
+        return;
+    };
+
+    let mut files = Vec::new();
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        if let Ok(metadata) = entry.metadata().await {
+            if metadata.is_file() {
+                let modified = metadata.modified().ok();
+                files.push((modified, metadata.len(), entry.path()));
+            }
+        }
+    }
+
+    let mut size: u64 = files.iter().map(|(_, len, _)| len).sum();
+    files.sort();
+    for (_, len, path) in files {
+        if size <= MAX_CACHE_SIZE {
+            break;
+        }
+        if tokio::fs::remove_file(&path).await.is_ok() {
+            size -= len;
+        }
+    }
+}
+
+/// Decode the percent encoded characters of a file url
+fn percent_decode(path: &str) -> String {
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_cached_art_the_same_every_time() {
+        // The names must never change, or the art already downloaded is left behind
+        assert_eq!(
+            hashed("https://i.scdn.co/image/ab67616d0000b273"),
+            "076a28bb988f7797bf69cfa5ec9aa5d551ef3f15"
+        );
+    }
+}
@@ -0,0 +1,140 @@
+use crate::BoxedError;
+use std::process::Stdio;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::process::Command;
+use tokio::sync::mpsc;
+
+/// Run pactl, which talks to PulseAudio as well as to PipeWire through pipewire-pulse
+async fn pactl(args: &[&str]) -> Result<String, BoxedError> {
+    // What pactl prints is translated, so it is asked for in English to be understood
+    let output = Command::new("pactl")
+        .args(args)
+        .env("LC_ALL", "C")
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .await?;
+
+    if !output.status.success() {
+        return Err(format!("pactl {} failed with {}", args.join(" "), output.status).into());
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Check if anything records from a microphone, leaving out what only listens to the output such as visualizers
+async fn microphone_in_use() -> Result<bool, BoxedError> {
+    let sources = pactl(&["list", "short", "sources"]).await?;
+    let monitors: Vec<&str> = sources
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split('\t');
+            let (index, name) = (fields.next()?, fields.next()?);
+            name.ends_with(".monitor").then_some(index)
+        })
+        .collect();
+
+    // The second field of a source output is the index of the source it records from
+    Ok(pactl(&["list", "short", "source-outputs"])
+        .await?
+        .lines()
+        .filter_map(|line| line.split('\t').nth(1))
+        .any(|source| !monitors.contains(&source)))
+}
+
+/// The processes that are playing sound right now, leaving out streams that are paused or muted
+async fn audible_processes() -> Result<Vec<u32>, BoxedError> {
+    let sink_inputs = pactl(&["list", "sink-inputs"]).await?;
+    let mut processes = Vec::new();
+
+    // Each stream is a block of properties starting with a line such as "Sink Input #42"
+    for stream in sink_inputs.split("Sink Input #").skip(1) {
+        let mut lines = stream.lines().map(str::trim);
+        if lines
+            .clone()
+            .any(|line| line == "Corked: yes" || line == "Mute: yes")
+        {
+            continue;
+        }
+        if let Some(pid) = lines.find_map(|line| line.strip_prefix("application.process.id = ")) {
+            if let Ok(pid) = pid.trim_matches('"').parse() {
+                processes.push(pid);
+            }
+        }
+    }
+
+    Ok(processes)
+}
+
+/// The parent of a process, which is the fourth field of its stat after the name in parentheses
+fn parent(pid: u32) -> Option<u32> {
+    let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    stat.rsplit_once(')')?
+        .1
+        .split_whitespace()
+        .nth(1)?
+        .parse()
+        .ok()
+}
+
+/// Check if a process or any of its children plays sound, since browsers play it from other processes
+pub async fn audible(pid: u32) -> Result<bool, BoxedError> {
+    Ok(audible_processes()
+        .await?
+        .into_iter()
+        .any(|mut process| loop {
+            if process == pid {
+                break true;
+            }
+            match parent(process) {
+                Some(next) if next > 1 => process = next,
+                _ => break false,
+            }
+        }))
+}
+
+/// Watch for recording to start and stop, such as when a call begins, and pass it on to the property stream
+pub async fn watch_microphone(sender: mpsc::Sender<bool>) -> Result<(), BoxedError> {
+    let mut subscriber = Command::new("pactl")
+        .arg("subscribe")
+        .env("LC_ALL", "C")
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .spawn()?;
+    let stdout = subscriber
+        .stdout
+        .take()
+        .ok_or("Failed to read the events of pactl")?;
+    let mut events = BufReader::new(stdout).lines();
+
+    // Only changes matter, so recording that is going on already is left alone
+    let mut in_use = microphone_in_use().await?;
+
+    while let Some(event) = events.next_line().await? {
+        // Such as "Event 'new' on source-output #42"
+        if !event.contains("source-output") {
+            continue;
+        }
+
+        let now_in_use = microphone_in_use().await?;
+        if now_in_use != in_use {
+            in_use = now_in_use;
+            // The property stream is gone once lizzy exits, and then there is nothing left to do
+            if sender.send(in_use).await.is_err() {
+                return Ok(());
+            }
+        }
+    }
+
+    Err("pactl stopped sending events".into())
+}
+
+/// Wait for the next change of the microphone being in use, or forever if it is not watched
+pub async fn next_change(receiver: &mut Option<mpsc::Receiver<bool>>) -> Option<bool> {
+    match receiver {
+        Some(receiver) => receiver.recv().await,
+        None => std::future::pending().await,
+    }
+}
@@ -0,0 +1,320 @@
+use crate::media::Metadata;
+use crate::options::Arguments;
+use crate::BoxedError;
+use crate::{
+    audio, get_property, is_ignored, list_mediaplayers, name_owner, parse_msg_args, sender_matches,
+    toggle_playback, unpack_metadata, State,
+};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use tracing::{debug, error, info, warn};
+use zbus::fdo::DBusProxy;
+use zbus::names::BusName;
+use zbus::zvariant::Value;
+use zbus::Connection;
+
+/// Check if the sender of a message is a mediaplayer that the user doesn't want autotoggle to react to
+pub async fn autotoggle_ignored(
+    proxy: &DBusProxy<'_>,
+    state: &State,
+    options: &Arguments,
+    sender_busname: &str,
+) -> bool {
+    match sender_matches(proxy, state, &options.autotoggle_ignore, sender_busname).await {
+        Ok(false) => false,
+        Ok(true) => {
+            debug!(sender = %sender_busname, event = "autotoggle", "Not toggling playback for {}, which is ignored", sender_busname);
+            true
+        }
+        Err(err) => {
+            // Better to not toggle at all than to toggle for a mediaplayer that should be ignored
+            error!("Failed to match the sender of a message: {}", err);
+            true
+        }
+    }
+}
+
+/// Toggle the playback of our mediaplayer to the opposite of what another mediaplayer reports
+pub async fn toggle_against(
+    connection: &Connection,
+    options: &Arguments,
+    state: &State,
+    changed_properties: &HashMap<&str, Value<'_>>,
+    sender_busname: &str,
+    mediaplayer_busname: &str,
+) -> Result<(), BoxedError> {
+    // We get the playbackstatus reported from the other mediaplayer
+    let media = parse_msg_args(
+        connection,
+        options,
+        state,
+        changed_properties,
+        sender_busname,
+    )
+    .await?;
+
+    // And pause our mediaplayer while it plays, or resume it once it stops
+    match media.playbackstatus.as_deref() {
+        Some("Playing") if !long_enough(options, sender_busname, media.metadata.length) => Ok(()),
+        Some("Playing") if !audible(connection, options, sender_busname).await => Ok(()),
+        Some("Playing") => {
+            interrupt(
+                connection,
+                options,
+                state,
+                sender_busname,
+                mediaplayer_busname,
+            )
+            .await
+        }
+        Some(_) => resume(connection, options, state, sender_busname).await,
+        None => Ok(()),
+    }
+}
+
+/// Pause our mediaplayer since another one started playing, remembering that lizzy was the one pausing it
+async fn interrupt(
+    connection: &Connection,
+    options: &Arguments,
+    state: &State,
+    other_busname: &str,
+    mediaplayer_busname: &str,
+) -> Result<(), BoxedError> {
+    let paused = state
+        .autopause
+        .lock()
+        .expect("Autopause lock poisoned")
+        .mediaplayer
+        .is_some();
+
+    // A mediaplayer that the user paused is left alone, so that it is not resumed later on
+    if !paused {
+        let playbackstatus: String =
+            get_property(connection, mediaplayer_busname, "PlaybackStatus")
+                .await?
+                .downcast()?;
+        if playbackstatus != "Playing" || !cooled_down(options, state, mediaplayer_busname) {
+            return Ok(());
+        }
+        toggle_playback(connection, mediaplayer_busname, "Pause").await?;
+    }
+
+    let mut autopause = state.autopause.lock().expect("Autopause lock poisoned");
+    autopause
+        .mediaplayer
+        .get_or_insert_with(|| mediaplayer_busname.to_string());
+    if !autopause
+        .interrupters
+        .iter()
+        .any(|interrupter| interrupter == other_busname)
+    {
+        autopause.interrupters.push(other_busname.to_string());
+    }
+
+    Ok(())
+}
+
+/// Check that autotoggle hasn't toggled a mediaplayer too recently, remembering this toggle if not
+fn cooled_down(options: &Arguments, state: &State, mediaplayer_busname: &str) -> bool {
+    let mut autopause = state.autopause.lock().expect("Autopause lock poisoned");
+    let now = Instant::now();
+    let cooldown = Duration::from_millis(options.autotoggle_cooldown);
+
+    if let Some(toggled) = autopause.toggled.get(mediaplayer_busname) {
+        if now.duration_since(*toggled) < cooldown {
+            // Toggling this often is most likely a loop, such as with another lizzy controlling the other mediaplayer
+            warn!(
+                player = mediaplayer_busname,
+                event = "autotoggle",
+                "Skipping autotoggle of {}, which was toggled {} ms ago",
+                mediaplayer_busname,
+                now.duration_since(*toggled).as_millis()
+            );
+            return false;
+        }
+    }
+
+    autopause
+        .toggled
+        .insert(mediaplayer_busname.to_string(), now);
+    true
+}
+
+/// Resume our mediaplayer once nothing that interrupted it is playing anymore, if lizzy paused it
+pub async fn resume(
+    connection: &Connection,
+    options: &Arguments,
+    state: &State,
+    other_busname: &str,
+) -> Result<(), BoxedError> {
+    let mediaplayer_busname = {
+        let mut autopause = state.autopause.lock().expect("Autopause lock poisoned");
+        autopause
+            .interrupters
+            .retain(|interrupter| interrupter != other_busname);
+        if autopause.interrupters.is_empty() {
+            autopause.mediaplayer.take()
+        } else {
+            None
+        }
+    };
+
+    if let Some(mediaplayer_busname) = mediaplayer_busname {
+        if cooled_down(options, state, &mediaplayer_busname) {
+            toggle_playback(connection, &mediaplayer_busname, "Play").await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Forget that autotoggle paused a mediaplayer, so that it is not resumed
+pub fn forget_autopause(state: &State, mediaplayer_busname: &str) {
+    let mut autopause = state.autopause.lock().expect("Autopause lock poisoned");
+    if autopause.mediaplayer.as_deref() == Some(mediaplayer_busname) {
+        autopause.mediaplayer = None;
+        autopause.interrupters.clear();
+    }
+}
+
+/// Pause our mediaplayer if another mediaplayer is playing
+pub async fn pause_if_playing(
+    connection: &Connection,
+    options: &Arguments,
+    state: &State,
+    other_busname: &str,
+    mediaplayer_busname: &str,
+) -> Result<(), BoxedError> {
+    let playbackstatus: String = get_property(connection, other_busname, "PlaybackStatus")
+        .await?
+        .downcast_ref()?;
+    if playbackstatus.as_str() != "Playing" {
+        return Ok(());
+    }
+
+    // The length only matters if the user set a threshold, so don't bother asking otherwise
+    if options.autotoggle_min_length.is_some() {
+        let metadata = match get_property(connection, other_busname, "Metadata").await {
+            Ok(metadata) => unpack_metadata(&metadata)?,
+            Err(_) => Metadata::default(),
+        };
+        if !long_enough(options, other_busname, metadata.length) {
+            return Ok(());
+        }
+    }
+
+    if !audible(connection, options, other_busname).await {
+        return Ok(());
+    }
+
+    interrupt(
+        connection,
+        options,
+        state,
+        other_busname,
+        mediaplayer_busname,
+    )
+    .await
+}
+
+/// Check if media is long enough to autotoggle for, where media of unknown length such as streams always is
+fn long_enough(options: &Arguments, sender_busname: &str, length: Option<i64>) -> bool {
+    let (Some(min_length), Some(length)) = (options.autotoggle_min_length, length) else {
+        return true;
+    };
+
+    // The length is in microseconds, and some mediaplayers send zero when it is unknown
+    let long_enough = length <= 0 || length as u64 > min_length.saturating_mul(1_000_000);
+    if !long_enough {
+        debug!(
+            sender = %sender_busname,
+            event = "autotoggle",
+            "Not toggling playback for {}, which plays something of only {} seconds",
+            sender_busname,
+            length / 1_000_000
+        );
+    }
+    long_enough
+}
+
+/// Pause every other mediaplayer that is playing, so that ours is the only one
+pub async fn pause_others(
+    connection: &Connection,
+    dbus_proxy: &DBusProxy<'_>,
+    options: &Arguments,
+    state: &State,
+    mediaplayer_busname: &str,
+) -> Result<(), BoxedError> {
+    let ours = name_owner(connection, state, mediaplayer_busname).await;
+
+    for name in list_mediaplayers(dbus_proxy).await? {
+        if is_ignored(options, &name) {
+            continue;
+        }
+
+        // A mediaplayer can own several names, and it shouldn't pause itself
+        let busname = format!("org.mpris.MediaPlayer2.{}", name);
+        if name_owner(connection, state, &busname).await == ours {
+            continue;
+        }
+
+        // The mediaplayer may have closed since we listed it, which is fine
+        let playing = match get_property(connection, &busname, "PlaybackStatus").await {
+            Ok(value) => value
+                .downcast_ref::<String>()
+                .is_ok_and(|status| status == "Playing"),
+            Err(_) => false,
+        };
+        if playing {
+            info!(player = %busname, event = "pause", "Pausing {} since {} started playing", busname, mediaplayer_busname);
+            toggle_playback(connection, &busname, "Pause").await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// How many times to look for the sound of a mediaplayer, which may start a bit after it reports playing
+const AUDIBLE_ATTEMPTS: u32 = 4;
+
+/// Check if a mediaplayer actually plays sound, when the user only wants autotoggle for that
+async fn audible(connection: &Connection, options: &Arguments, sender_busname: &str) -> bool {
+    if !options.autotoggle_audible {
+        return true;
+    }
+
+    let pid = match DBusProxy::new(connection).await {
+        Ok(proxy) => match BusName::try_from(sender_busname) {
+            Ok(busname) => proxy.get_connection_unix_process_id(busname).await.ok(),
+            Err(_) => None,
+        },
+        Err(_) => None,
+    };
+    // Without knowing the process there is no telling, so it is better to toggle than to miss it
+    let Some(pid) = pid else {
+        warn!(sender = %sender_busname, event = "autotoggle", "Failed to find the process of {}", sender_busname);
+        return true;
+    };
+
+    for attempt in 1..=AUDIBLE_ATTEMPTS {
+        match audio::audible(pid).await {
+            Ok(true) => return true,
+            Ok(false) if attempt < AUDIBLE_ATTEMPTS => {
+                tokio::time::sleep(Duration::from_millis(250)).await
+            }
+            Ok(false) => {}
+            Err(err) => {
+                warn!(event = "autotoggle", "Failed to check for sound: {}", err);
+                return true;
+            }
+        }
+    }
+
+    debug!(
+        sender = %sender_busname,
+        event = "autotoggle",
+        "Not toggling playback for {}, which plays no sound",
+        sender_busname
+    );
+    false
+}
@@ -0,0 +1,124 @@
+use crate::media::{Media, Metadata};
+use crate::options::Arguments;
+use crate::BoxedError;
+use std::path::PathBuf;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::UnixStream;
+
+/// The socket of cmus, found the same way as cmus-remote does
+fn socket_path() -> Option<PathBuf> {
+    if let Some(path) = std::env::var_os("CMUS_SOCKET") {
+        return Some(PathBuf::from(path));
+    }
+
+    match std::env::var_os("XDG_RUNTIME_DIR") {
+        Some(runtime_dir) if !runtime_dir.is_empty() => {
+            Some(PathBuf::from(runtime_dir).join("cmus-socket"))
+        }
+        _ => Some(
+            PathBuf::from(std::env::var_os("HOME")?)
+                .join(".config")
+                .join("cmus")
+                .join("socket"),
+        ),
+    }
+}
+
+/// Ask cmus for its status, which it ends with an empty line
+async fn status() -> Result<Vec<String>, BoxedError> {
+    let path = socket_path().ok_or("Failed to find the socket of cmus")?;
+    let mut stream = UnixStream::connect(&path)
+        .await
+        .map_err(|err| format!("could not connect to cmus on {}: {}", path.display(), err))?;
+    stream.write_all(b"status\n").await?;
+
+    let mut lines = BufReader::new(stream).lines();
+    let mut status = Vec::new();
+    while let Some(line) = lines.next_line().await? {
+        if line.is_empty() {
+            break;
+        }
+        status.push(line);
+    }
+
+    Ok(status)
+}
+
+/// Get the current media from cmus, or none if nothing is loaded
+pub async fn media(options: &Arguments) -> Result<Option<Media>, BoxedError> {
+    let status = status().await?;
+
+    // Lines are a key followed by its value, where tags and settings have a prefix of their own
+    let get = |key: &str| {
+        status.iter().find_map(|line| {
+            line.strip_prefix(key)
+                .and_then(|value| value.strip_prefix(' '))
+                .map(String::from)
+        })
+    };
+    let tag = |name: &str| get(&format!("tag {}", name)).filter(|value| !value.is_empty());
+    let setting = |name: &str| get(&format!("set {}", name)).map(|value| value == "true");
+    let seconds = |key: &str| {
+        get(key)
+            .and_then(|value| value.parse::<i64>().ok())
+            .filter(|seconds| *seconds >= 0)
+            .map(|seconds| seconds * 1_000_000)
+    };
+
+    // Streams have no file, and either one is needed to have anything to show
+    let Some(source) = get("file").or_else(|| get("stream")) else {
+        return Ok(None);
+    };
+
+    let artist = tag("artist");
+    let mut metadata = Metadata {
+        artists: artist.iter().cloned().collect(),
+        artist,
+        // Files without tags have no title, so show the name of the file instead
+        title: tag("title").or_else(|| source.rsplit('/').next().map(String::from)),
+        album: tag("album"),
+        albumartist: tag("albumartist"),
+        length: seconds("duration"),
+        // Only streams have a url, files are just a path
+        url: Some(source.clone()).filter(|source| source.contains("://")),
+        trackid: Some(source),
+        ..Metadata::default()
+    };
+    metadata.clean(options);
+
+    let playbackstatus = match get("status").as_deref() {
+        Some("playing") => "Playing",
+        Some("paused") => "Paused",
+        _ => "Stopped",
+    };
+
+    let loop_status = match (setting("repeat_current"), setting("repeat")) {
+        (Some(true), _) => "Track",
+        (_, Some(true)) => "Playlist",
+        _ => "None",
+    };
+
+    // The volume is given per channel, of which the louder one is taken
+    let volume = ["left", "right"]
+        .iter()
+        .filter_map(|channel| get(&format!("set vol_{}", channel))?.parse::<f64>().ok())
+        .reduce(f64::max)
+        .map(|volume| volume / 100.0);
+
+    let mut media = Media::new(
+        metadata,
+        Some(playbackstatus.to_string()),
+        seconds("position"),
+        // Newer versions of cmus shuffle either tracks or albums rather than just turning it on
+        get("set shuffle").map(|shuffle| shuffle != "false" && shuffle != "off"),
+        Some(loop_status.to_string()),
+        volume,
+        Some(String::from("cmus")),
+    );
+    media.capabilities = crate::CAPABILITIES
+        .iter()
+        .map(|(_, class)| *class)
+        .collect();
+
+    Ok(Some(media))
+}
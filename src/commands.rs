@@ -0,0 +1,454 @@
+use crate::docs::{self, Shell};
+use crate::options::Arguments;
+use crate::{
+    get_property, get_root_property, list_mediaplayers, set_property, toggle_playback, BoxedError,
+    State,
+};
+use zbus::fdo::DBusProxy;
+use zbus::zvariant::{Dict, ObjectPath, Value};
+use zbus::{Connection, Proxy};
+
+/// The loop statuses of MPRIS, in the order `lizzy loop` cycles through them
+const LOOP_STATUSES: [&str; 3] = ["None", "Track", "Playlist"];
+
+/// Resolve the mediaplayer to act on, with nothing cached since a command only runs once
+async fn resolve(
+    connection: &Connection,
+    options: &Arguments,
+) -> Result<Option<String>, BoxedError> {
+    crate::resolve_mediaplayer(connection, options, &State::default()).await
+}
+
+/// One-shot commands that do their thing and exit, rather than streaming output
+pub enum Command {
+    Players,
+    PlayPause,
+    Next,
+    Previous,
+    Stop,
+    Shuffle,
+    Loop(Option<String>),
+    Volume(VolumeChange),
+    Seek(i64),
+    Position(i64),
+    Raise,
+    Quit,
+    Launch(Option<String>),
+    Control(String),
+    Completions(Shell),
+    Man,
+}
+
+/// A change of volume in percent, either relative such as +5 or absolute such as 40
+pub enum VolumeChange {
+    By(f64),
+    To(f64),
+}
+
+impl std::str::FromStr for VolumeChange {
+    type Err = String;
+
+    fn from_str(change: &str) -> Result<Self, Self::Err> {
+        let invalid = |_| format!("invalid volume '{}', expected such as +5, -5 or 40", change);
+
+        if change.starts_with(['+', '-']) {
+            change.parse().map(VolumeChange::By).map_err(invalid)
+        } else {
+            change.parse().map(VolumeChange::To).map_err(invalid)
+        }
+    }
+}
+
+impl Command {
+    /// Parse a command from its name, taking any arguments it needs from the command line
+    pub fn parse(name: &str, pargs: &mut pico_args::Arguments) -> Result<Self, pico_args::Error> {
+        match name {
+            "players" => Ok(Command::Players),
+            "play-pause" => Ok(Command::PlayPause),
+            "next" => Ok(Command::Next),
+            "previous" => Ok(Command::Previous),
+            "stop" => Ok(Command::Stop),
+            "shuffle" => Ok(Command::Shuffle),
+            "loop" => {
+                let status: Option<String> = pargs.opt_free_from_str()?;
+                match status {
+                    Some(status) if !LOOP_STATUSES.contains(&status.as_str()) => {
+                        Err(pico_args::Error::ArgumentParsingFailed {
+                            cause: format!(
+                                "unknown loop status '{}', expected None, Track or Playlist",
+                                status
+                            ),
+                        })
+                    }
+                    status => Ok(Command::Loop(status)),
+                }
+            }
+            "volume" => Ok(Command::Volume(pargs.free_from_str()?)),
+            "raise" => Ok(Command::Raise),
+            "quit" => Ok(Command::Quit),
+            "launch" => Ok(Command::Launch(pargs.opt_free_from_str()?)),
+            "seek" => Ok(Command::Seek(pargs.free_from_fn(parse_offset)?)),
+            "position" => Ok(Command::Position(pargs.free_from_fn(|time| {
+                parse_time(time).ok_or(format!(
+                    "invalid position '{}', expected such as 1:23",
+                    time
+                ))
+            })?)),
+            "control" => {
+                // The request is whatever follows the command, such as `switch spotify`
+                let mut words: Vec<String> = Vec::new();
+                while let Some(word) = pargs.opt_free_from_str()? {
+                    words.push(word);
+                }
+                if words.is_empty() {
+                    return Err(pico_args::Error::MissingArgument);
+                }
+                Ok(Command::Control(words.join(" ")))
+            }
+            "completions" => Ok(Command::Completions(pargs.free_from_str()?)),
+            "man" => Ok(Command::Man),
+            _ => Err(pico_args::Error::ArgumentParsingFailed {
+                cause: format!("unknown command '{}'", name),
+            }),
+        }
+    }
+
+    /// Run the command
+    pub async fn run(
+        &self,
+        connection: &Connection,
+        options: &Arguments,
+    ) -> Result<(), BoxedError> {
+        match self {
+            Command::Players => list_players(connection).await,
+            Command::PlayPause => control(connection, options, "PlayPause").await,
+            Command::Next => control(connection, options, "Next").await,
+            Command::Previous => control(connection, options, "Previous").await,
+            Command::Stop => control(connection, options, "Stop").await,
+            Command::Shuffle => toggle_shuffle(connection, options).await,
+            Command::Loop(status) => set_loop(connection, options, status.as_deref()).await,
+            Command::Volume(change) => set_volume(connection, options, change).await,
+            Command::Seek(offset) => seek(connection, options, *offset).await,
+            Command::Position(position) => set_position(connection, options, *position).await,
+            Command::Raise => call_root(connection, options, "Raise", "CanRaise").await,
+            Command::Quit => call_root(connection, options, "Quit", "CanQuit").await,
+            Command::Launch(name) => launch(connection, options, name.as_deref()).await,
+            Command::Control(request) => crate::control::send(options, request).await,
+            // These are printed before connecting, see `docs`
+            Command::Completions(_) | Command::Man => Ok(()),
+        }
+    }
+
+    /// The completions or the man page, which need no session bus to print
+    pub fn docs(&self) -> Option<String> {
+        match self {
+            Command::Completions(shell) => Some(docs::completions(*shell)),
+            Command::Man => Some(docs::man()),
+            _ => None,
+        }
+    }
+}
+
+/// Parse a time such as 83, 83s, 1:23 or 1:02:03 into microseconds
+fn parse_time(time: &str) -> Option<i64> {
+    let time = time.strip_suffix('s').unwrap_or(time);
+    if time.is_empty() || time.split(':').count() > 3 {
+        return None;
+    }
+
+    time.split(':')
+        .try_fold(0, |seconds: i64, part| {
+            // Signs are only allowed in front of an offset, not in each part
+            if !part.bytes().all(|byte| byte.is_ascii_digit()) {
+                return None;
+            }
+            Some(seconds * 60 + i64::from(part.parse::<u32>().ok()?))
+        })
+        .map(|seconds| seconds * 1_000_000)
+}
+
+/// Parse an offset to seek by, such as +10s or -1:00, where no sign means forward
+fn parse_offset(offset: &str) -> Result<i64, String> {
+    let invalid = || format!("invalid offset '{}', expected such as +10s or -10s", offset);
+
+    match offset.strip_prefix('-') {
+        Some(time) => parse_time(time).map(|time| -time).ok_or_else(invalid),
+        None => parse_time(offset.strip_prefix('+').unwrap_or(offset)).ok_or_else(invalid),
+    }
+}
+
+/// Print the running mediaplayers along with their identity and playback status
+async fn list_players(connection: &Connection) -> Result<(), BoxedError> {
+    let dbus_proxy = DBusProxy::new(connection).await?;
+
+    let mut rows: Vec<[String; 3]> = Vec::new();
+
+    for name in list_mediaplayers(&dbus_proxy).await? {
+        let bus_name = format!("org.mpris.MediaPlayer2.{}", name);
+
+        // Not all mediaplayers are well behaved, so missing properties are just left blank
+        let identity: String = match get_root_property(connection, &bus_name, "Identity").await {
+            Ok(value) => value.downcast().unwrap_or_default(),
+            Err(_) => String::new(),
+        };
+        let playbackstatus: String =
+            match get_property(connection, &bus_name, "PlaybackStatus").await {
+                Ok(value) => value.downcast().unwrap_or_default(),
+                Err(_) => String::new(),
+            };
+
+        rows.push([name, identity, playbackstatus]);
+    }
+
+    // Align the columns to make it easier to read
+    let name_width = rows
+        .iter()
+        .map(|row| row[0].len())
+        .max()
+        .unwrap_or_default();
+    let identity_width = rows
+        .iter()
+        .map(|row| row[1].len())
+        .max()
+        .unwrap_or_default();
+
+    for [name, identity, playbackstatus] in rows {
+        println!(
+            "{:<name_width$}  {:<identity_width$}  {}",
+            name, identity, playbackstatus
+        );
+    }
+
+    Ok(())
+}
+
+/// Call a method on the player interface of the resolved mediaplayer
+async fn control(
+    connection: &Connection,
+    options: &Arguments,
+    method: &str,
+) -> Result<(), BoxedError> {
+    let Some(busname) = resolve(connection, options).await? else {
+        // Clicking the module when the mediaplayer is closed can start it instead
+        if options.launch_on_click {
+            return launch(connection, options, None).await;
+        }
+        return Err("No matching mediaplayer is running".into());
+    };
+
+    toggle_playback(connection, &busname, method).await
+}
+
+/// Turn shuffle of the resolved mediaplayer on or off, whichever it is not
+async fn toggle_shuffle(connection: &Connection, options: &Arguments) -> Result<(), BoxedError> {
+    let busname = resolve(connection, options)
+        .await?
+        .ok_or("No matching mediaplayer is running")?;
+
+    let shuffle: bool = get_property(connection, &busname, "Shuffle")
+        .await?
+        .downcast()?;
+
+    set_property(connection, &busname, "Shuffle", Value::from(!shuffle)).await
+}
+
+/// Set the loop status of the resolved mediaplayer, or go to the next one if none is given
+async fn set_loop(
+    connection: &Connection,
+    options: &Arguments,
+    status: Option<&str>,
+) -> Result<(), BoxedError> {
+    let busname = resolve(connection, options)
+        .await?
+        .ok_or("No matching mediaplayer is running")?;
+
+    let status = match status {
+        Some(status) => status,
+        None => {
+            let current: String = get_property(connection, &busname, "LoopStatus")
+                .await?
+                .downcast()?;
+            let index = LOOP_STATUSES
+                .iter()
+                .position(|status| *status == current)
+                .unwrap_or_default();
+            LOOP_STATUSES[(index + 1) % LOOP_STATUSES.len()]
+        }
+    };
+
+    set_property(connection, &busname, "LoopStatus", Value::from(status)).await
+}
+
+/// Change the volume of the resolved mediaplayer, keeping it between 0 and 100 percent
+async fn set_volume(
+    connection: &Connection,
+    options: &Arguments,
+    change: &VolumeChange,
+) -> Result<(), BoxedError> {
+    let busname = resolve(connection, options)
+        .await?
+        .ok_or("No matching mediaplayer is running")?;
+
+    // MPRIS has the volume as a fraction, where 1.0 is full volume
+    let volume = match change {
+        VolumeChange::By(percent) => {
+            let current: f64 = get_property(connection, &busname, "Volume")
+                .await?
+                .downcast()?;
+            current + percent / 100.0
+        }
+        VolumeChange::To(percent) => percent / 100.0,
+    };
+
+    set_property(
+        connection,
+        &busname,
+        "Volume",
+        Value::from(volume.clamp(0.0, 1.0)),
+    )
+    .await
+}
+
+/// Resolve the mediaplayer to seek in, making sure that it can
+async fn seekable_mediaplayer(
+    connection: &Connection,
+    options: &Arguments,
+) -> Result<String, BoxedError> {
+    let busname = resolve(connection, options)
+        .await?
+        .ok_or("No matching mediaplayer is running")?;
+
+    let can_seek: bool = get_property(connection, &busname, "CanSeek")
+        .await?
+        .downcast()?;
+    if !can_seek {
+        return Err("The mediaplayer can't seek".into());
+    }
+
+    Ok(busname)
+}
+
+/// Call a method with arguments on the player interface of a mediaplayer, such as Seek
+async fn call<B>(
+    connection: &Connection,
+    busname: &str,
+    method: &str,
+    body: &B,
+) -> Result<(), BoxedError>
+where
+    B: serde::Serialize + zbus::zvariant::DynamicType,
+{
+    let proxy = Proxy::new(
+        connection,
+        busname,
+        "/org/mpris/MediaPlayer2",
+        "org.mpris.MediaPlayer2.Player",
+    )
+    .await?;
+
+    Ok(proxy.call_noreply(method, body).await?)
+}
+
+/// Seek forward or backward in the track of the resolved mediaplayer
+async fn seek(connection: &Connection, options: &Arguments, offset: i64) -> Result<(), BoxedError> {
+    let busname = seekable_mediaplayer(connection, options).await?;
+
+    call(connection, &busname, "Seek", &(offset,)).await
+}
+
+/// Go to a position in the track of the resolved mediaplayer
+async fn set_position(
+    connection: &Connection,
+    options: &Arguments,
+    position: i64,
+) -> Result<(), BoxedError> {
+    let busname = seekable_mediaplayer(connection, options).await?;
+
+    // The trackid makes sure that the position is not set in another track than the one asked for
+    let metadata = get_property(connection, &busname, "Metadata").await?;
+    let dict: &Dict = metadata.downcast_ref()?;
+    let trackid: ObjectPath = dict
+        .get(&"mpris:trackid")?
+        .ok_or("The mediaplayer has no trackid to set the position in")?;
+
+    call(connection, &busname, "SetPosition", &(trackid, position)).await
+}
+
+/// Call a method on the root interface of the resolved mediaplayer, if it reports being capable of it
+async fn call_root(
+    connection: &Connection,
+    options: &Arguments,
+    method: &str,
+    capability: &str,
+) -> Result<(), BoxedError> {
+    let busname = resolve(connection, options)
+        .await?
+        .ok_or("No matching mediaplayer is running")?;
+
+    // A mediaplayer without a window, such as mpd, can't be raised, and some can't be quit
+    let capable: bool = get_root_property(connection, &busname, capability)
+        .await?
+        .downcast()?;
+    if !capable {
+        return Err(format!("The mediaplayer reports {} as false", capability).into());
+    }
+
+    let proxy = Proxy::new(
+        connection,
+        busname.as_str(),
+        "/org/mpris/MediaPlayer2",
+        "org.mpris.MediaPlayer2",
+    )
+    .await?;
+
+    Ok(proxy.call_noreply(method, &()).await?)
+}
+
+/// Start the given mediaplayer, or the first one given with --mediaplayer
+async fn launch(
+    connection: &Connection,
+    options: &Arguments,
+    name: Option<&str>,
+) -> Result<(), BoxedError> {
+    let mediaplayers = options.mediaplayers();
+    let name = match name {
+        Some(name) => name,
+        None => mediaplayers
+            .first()
+            .and_then(|pattern| pattern.exact_name())
+            .ok_or("Give a mediaplayer to launch, or a --mediaplayer that is not a pattern")?,
+    };
+
+    crate::launch::launch(connection, name).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_times() {
+        assert_eq!(parse_time("83"), Some(83_000_000));
+        assert_eq!(parse_time("83s"), Some(83_000_000));
+        assert_eq!(parse_time("1:23"), Some(83_000_000));
+        assert_eq!(parse_time("1:02:03"), Some(3_723_000_000));
+        assert_eq!(parse_time("0"), Some(0));
+    }
+
+    #[test]
+    fn rejects_invalid_times() {
+        for time in ["", "s", "1:2:3:4", "+1", "1:-2", "1::2", "abc", "1.5"] {
+            assert_eq!(parse_time(time), None, "'{}' should not parse", time);
+        }
+    }
+
+    #[test]
+    fn parses_offsets() {
+        assert_eq!(parse_offset("+10s"), Ok(10_000_000));
+        assert_eq!(parse_offset("10"), Ok(10_000_000));
+        assert_eq!(parse_offset("-1:00"), Ok(-60_000_000));
+        assert!(parse_offset("--1").is_err());
+        assert!(parse_offset("+-1").is_err());
+        assert!(parse_offset("+").is_err());
+    }
+}
@@ -0,0 +1,205 @@
+use crate::matcher::Pattern;
+use crate::template::Template;
+use crate::xdg;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+
+/// The metadata that replace rules can change
+#[derive(Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Field {
+    Artist,
+    Title,
+    Album,
+}
+
+/// A replace rule as it is written in the config file
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawRule {
+    field: Field,
+    pattern: String,
+    #[serde(default)]
+    replacement: String,
+}
+
+/// The config file as it is written
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct RawConfig {
+    format: Option<String>,
+    mediaplayer: Option<Vec<String>>,
+    ignore: Option<Vec<String>>,
+    replace: Vec<RawRule>,
+    listenbrainz: Option<ListenBrainz>,
+    mqtt: Option<Mqtt>,
+}
+
+/// Where to submit listens to, with the user token found in the settings of ListenBrainz
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ListenBrainz {
+    pub token: String,
+    // Self-hosted instances have an API of their own
+    #[serde(default = "default_listenbrainz_url")]
+    pub url: String,
+}
+
+fn default_listenbrainz_url() -> String {
+    String::from("https://api.listenbrainz.org")
+}
+
+/// The MQTT broker to publish what is playing to, for home automation such as Home Assistant
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Mqtt {
+    #[serde(default = "default_mqtt_host")]
+    pub host: String,
+    #[serde(default = "default_mqtt_port")]
+    pub port: u16,
+    #[serde(default = "default_mqtt_topic")]
+    pub topic: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+fn default_mqtt_host() -> String {
+    String::from("localhost")
+}
+
+fn default_mqtt_port() -> u16 {
+    1883
+}
+
+fn default_mqtt_topic() -> String {
+    String::from("lizzy/now-playing")
+}
+
+/// Replace whatever matches a regular expression in some metadata, such as " - Topic" in artists from YouTube
+pub struct Rule {
+    pub field: Field,
+    pattern: Regex,
+    replacement: String,
+}
+
+/// Noise that is often added to titles of videos, such as `(Official Video)`, `[HD]` or `- YouTube`
+pub static TITLE_CLEANER: Lazy<Vec<Rule>> = Lazy::new(|| {
+    [
+        r"(?i)\s*[(\[]\s*(official\s+)?(music\s+)?(video|audio|lyrics?|lyric\s+video|visuali[sz]er)(\s+(hd|hq|4k))?\s*[)\]]",
+        r"(?i)\s*[(\[]\s*(hd|hq|4k|1080p|720p)\s*[)\]]",
+        r"(?i)\s*-\s*youtube(\s+music)?\s*$",
+    ]
+    .iter()
+    .map(|pattern| Rule {
+        field: Field::Title,
+        pattern: Regex::new(pattern).expect("Built-in patterns are valid"),
+        replacement: String::new(),
+    })
+    .collect()
+});
+
+impl Rule {
+    /// Apply the rule to a value, where $1 and such in the replacement refer to the groups of the pattern
+    pub fn apply(&self, value: &str) -> String {
+        self.pattern
+            .replace_all(value, self.replacement.as_str())
+            .into_owned()
+    }
+}
+
+/// What is read from the config file, for things that are too much for command line options
+/// and for settings that can change while lizzy runs
+#[derive(Default)]
+pub struct Config {
+    pub format: Option<Template>,
+    pub mediaplayers: Option<Vec<Pattern>>,
+    pub ignore: Option<Vec<Pattern>>,
+    pub replace: Vec<Rule>,
+    pub listenbrainz: Option<ListenBrainz>,
+    pub mqtt: Option<Mqtt>,
+}
+
+/// The default path of the config file, following the XDG base directories
+fn default_path() -> Option<PathBuf> {
+    Some(xdg::config_home()?.join("lizzy").join("config.toml"))
+}
+
+/// The config file given by the user, or the default one whether it exists or not
+pub fn path(path: Option<&Path>) -> Option<PathBuf> {
+    match path {
+        Some(path) => Some(path.to_path_buf()),
+        None => default_path(),
+    }
+}
+
+/// Read the config file given by the user, or the default one if it exists
+pub fn load(path: Option<&Path>, case_insensitive: bool) -> Result<Config, String> {
+    let path = match path {
+        Some(path) => path.to_path_buf(),
+        None => match default_path() {
+            Some(path) if path.exists() => path,
+            // Having no config file is fine when none was asked for
+            _ => return Ok(Config::default()),
+        },
+    };
+
+    let contents = std::fs::read_to_string(&path)
+        .map_err(|err| format!("could not read {}: {}", path.display(), err))?;
+    let raw: RawConfig = toml::from_str(&contents)
+        .map_err(|err| format!("invalid config in {}: {}", path.display(), err))?;
+
+    let replace = raw
+        .replace
+        .into_iter()
+        .map(|rule| {
+            Ok(Rule {
+                field: rule.field,
+                pattern: Regex::new(&rule.pattern).map_err(|err| {
+                    format!(
+                        "invalid pattern '{}' in {}: {}",
+                        rule.pattern,
+                        path.display(),
+                        err
+                    )
+                })?,
+                replacement: rule.replacement,
+            })
+        })
+        .collect::<Result<_, String>>()?;
+
+    let format = raw
+        .format
+        .map(|format| {
+            format
+                .parse()
+                .map_err(|err| format!("invalid format in {}: {}", path.display(), err))
+        })
+        .transpose()?;
+
+    // Mediaplayers are listed the same way as on the command line, patterns included
+    let patterns = |names: Option<Vec<String>>| {
+        names
+            .map(|names| {
+                names
+                    .iter()
+                    .map(|name| {
+                        Pattern::new(name, case_insensitive).map_err(|err| {
+                            format!("invalid pattern '{}' in {}: {}", name, path.display(), err)
+                        })
+                    })
+                    .collect::<Result<Vec<_>, String>>()
+            })
+            .transpose()
+    };
+
+    Ok(Config {
+        format,
+        mediaplayers: patterns(raw.mediaplayer)?,
+        ignore: patterns(raw.ignore)?,
+        replace,
+        listenbrainz: raw.listenbrainz,
+        mqtt: raw.mqtt,
+    })
+}
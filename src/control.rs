@@ -0,0 +1,144 @@
+use crate::options::Arguments;
+use crate::{accept_failed, BoxedError};
+use std::path::{Path, PathBuf};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::{mpsc, oneshot};
+use tracing::error;
+
+/// Requests that can be sent to a running lizzy over the control socket
+pub enum Request {
+    Refresh,
+    Call(&'static str),
+    Switch(Option<String>),
+}
+
+impl Request {
+    /// Parse a request from a line received on the control socket
+    fn parse(line: &str) -> Result<Self, String> {
+        let mut words = line.split_whitespace();
+
+        match (words.next(), words.next()) {
+            (Some("refresh"), None) => Ok(Request::Refresh),
+            (Some("play-pause"), None) => Ok(Request::Call("PlayPause")),
+            (Some("next"), None) => Ok(Request::Call("Next")),
+            (Some("previous"), None) => Ok(Request::Call("Previous")),
+            (Some("stop"), None) => Ok(Request::Call("Stop")),
+            (Some("switch"), name) => Ok(Request::Switch(name.map(String::from))),
+            _ => Err(format!("unknown request '{}'", line.trim())),
+        }
+    }
+}
+
+/// A request along with a way to tell the client how it went
+pub type ControlMessage = (Request, oneshot::Sender<Result<(), String>>);
+
+/// The path of the control socket, either defined by the user or in the runtime directory
+pub fn socket_path(options: &Arguments) -> Result<PathBuf, BoxedError> {
+    match &options.socket {
+        Some(path) => Ok(path.clone()),
+        None => {
+            let runtime_dir =
+                std::env::var_os("XDG_RUNTIME_DIR").ok_or("XDG_RUNTIME_DIR is not set")?;
+            Ok(PathBuf::from(runtime_dir).join("lizzy.sock"))
+        }
+    }
+}
+
+/// Bind the control socket, removing one left behind by a lizzy that is no longer running
+pub async fn bind(path: &Path) -> Result<UnixListener, BoxedError> {
+    if path.exists() {
+        if UnixStream::connect(path).await.is_ok() {
+            return Err(format!("Another lizzy is already listening on {}", path.display()).into());
+        }
+        std::fs::remove_file(path)?;
+    }
+
+    Ok(UnixListener::bind(path)?)
+}
+
+/// Accept clients on the control socket and pass their requests on to the property stream
+pub async fn serve(
+    listener: UnixListener,
+    sender: mpsc::Sender<ControlMessage>,
+) -> Result<(), BoxedError> {
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(err) => {
+                accept_failed("control socket", err).await;
+                continue;
+            }
+        };
+        let sender = sender.clone();
+
+        // Handle each client separately so that a slow client does not block the others
+        tokio::spawn(async move {
+            if let Err(err) = handle_client(stream, sender).await {
+                error!("Control client failed: {}", err);
+            }
+        });
+    }
+}
+
+/// Read requests from a client and reply with the result of each
+async fn handle_client(
+    stream: UnixStream,
+    sender: mpsc::Sender<ControlMessage>,
+) -> Result<(), BoxedError> {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+
+    while let Some(line) = lines.next_line().await? {
+        let result = match Request::parse(&line) {
+            Ok(request) => {
+                let (reply_sender, reply) = oneshot::channel();
+                sender.send((request, reply_sender)).await?;
+                reply.await?
+            }
+            Err(err) => Err(err),
+        };
+
+        let reply = match result {
+            Ok(()) => String::from("ok\n"),
+            Err(err) => format!("error: {}\n", err),
+        };
+        writer.write_all(reply.as_bytes()).await?;
+    }
+
+    Ok(())
+}
+
+/// Wait for the next control message, or forever if there is no control socket
+pub async fn next_message(
+    receiver: &mut Option<mpsc::Receiver<ControlMessage>>,
+) -> Option<ControlMessage> {
+    match receiver {
+        Some(receiver) => receiver.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Send a request to a running lizzy and wait for its reply
+pub async fn send(options: &Arguments, request: &str) -> Result<(), BoxedError> {
+    let path = socket_path(options)?;
+    let stream = UnixStream::connect(&path)
+        .await
+        .map_err(|err| format!("Could not connect to lizzy at {}: {}", path.display(), err))?;
+
+    let (reader, mut writer) = stream.into_split();
+    writer
+        .write_all(format!("{}\n", request).as_bytes())
+        .await?;
+
+    let reply = BufReader::new(reader)
+        .lines()
+        .next_line()
+        .await?
+        .ok_or("No reply from lizzy")?;
+
+    match reply.strip_prefix("error: ") {
+        Some(err) => Err(err.to_string().into()),
+        None => Ok(()),
+    }
+}
@@ -0,0 +1,228 @@
+use crate::media::Media;
+use crate::BoxedError;
+use serde_json::{json, Value};
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::UnixStream;
+use tokio::sync::watch;
+use tracing::{debug, warn};
+
+/// The opcodes of the IPC protocol of Discord
+const HANDSHAKE: u32 = 0;
+const FRAME: u32 = 1;
+
+/// The activity type that shows as "Listening to"
+const LISTENING: u32 = 2;
+
+/// How far the timestamps may drift before Discord is told again, since it limits how often it can be updated
+const DRIFT_SECONDS: u64 = 2;
+
+/// What is shown as the Rich Presence on Discord
+#[derive(Clone)]
+pub struct Presence {
+    title: String,
+    artist: Option<String>,
+    album: Option<String>,
+    // Only known while playing, so that Discord doesn't keep counting while paused
+    start: Option<u64>,
+    end: Option<u64>,
+}
+
+impl Presence {
+    /// The presence of the media, or none if nothing is playing or paused
+    pub fn from_media(media: Option<&Media>) -> Option<Self> {
+        let media = media?;
+        let title = media.metadata.title.clone()?;
+
+        let (start, end) = match media.playbackstatus.as_deref() {
+            Some("Playing") => {
+                let now = SystemTime::now().duration_since(UNIX_EPOCH).ok()?.as_secs();
+                let position = media.position.unwrap_or_default().max(0) as u64 / 1_000_000;
+                let start = now.saturating_sub(position);
+                let end = media
+                    .metadata
+                    .length
+                    .filter(|length| *length > 0)
+                    .map(|length| start + length as u64 / 1_000_000);
+                (Some(start), end)
+            }
+            Some("Paused") => (None, None),
+            _ => return None,
+        };
+
+        Some(Presence {
+            title,
+            artist: media.metadata.artist.clone(),
+            album: media.metadata.album.clone(),
+            start,
+            end,
+        })
+    }
+
+    /// Check if the presence shows the same as another one, allowing the timestamps to drift a little
+    fn same_as(&self, other: &Self) -> bool {
+        let close = |a: Option<u64>, b: Option<u64>| match (a, b) {
+            (Some(a), Some(b)) => a.abs_diff(b) <= DRIFT_SECONDS,
+            (a, b) => a == b,
+        };
+
+        self.title == other.title
+            && self.artist == other.artist
+            && self.album == other.album
+            && close(self.start, other.start)
+            && close(self.end, other.end)
+    }
+
+    fn activity(&self) -> Value {
+        let mut activity = json!({
+            "type": LISTENING,
+            "details": self.title,
+        });
+
+        // Discord wants at least two characters, or it refuses the whole activity
+        if let Some(artist) = self.artist.as_ref().filter(|artist| artist.len() >= 2) {
+            activity["state"] = artist.as_str().into();
+        }
+        if let Some(album) = self.album.as_ref().filter(|album| album.len() >= 2) {
+            activity["assets"] = json!({ "large_text": album });
+        }
+        if let Some(start) = self.start {
+            activity["timestamps"] = json!({ "start": start });
+            if let Some(end) = self.end {
+                activity["timestamps"]["end"] = end.into();
+            }
+        }
+
+        activity
+    }
+}
+
+/// Pass on a change of the media to the publisher, unless Discord shows the same thing already
+pub fn update(sender: &watch::Sender<Option<Presence>>, media: Option<&Media>) {
+    let presence = Presence::from_media(media);
+
+    sender.send_if_modified(|current| {
+        let same = match (current.as_ref(), presence.as_ref()) {
+            (Some(current), Some(presence)) => current.same_as(presence),
+            (current, presence) => current.is_none() && presence.is_none(),
+        };
+        if !same {
+            current.clone_from(&presence);
+        }
+        !same
+    });
+}
+
+/// The sockets Discord may listen on, including those of the Flatpak and Snap packages
+fn socket_paths() -> Vec<PathBuf> {
+    let base = std::env::var_os("XDG_RUNTIME_DIR")
+        .or_else(|| std::env::var_os("TMPDIR"))
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("/tmp"));
+
+    ["", "app/com.discordapp.Discord", "snap.discord"]
+        .iter()
+        .flat_map(|dir| {
+            let dir = base.join(dir);
+            (0..10).map(move |i| dir.join(format!("discord-ipc-{}", i)))
+        })
+        .collect()
+}
+
+/// A connection to the IPC socket of a running Discord
+struct Client {
+    stream: UnixStream,
+}
+
+impl Client {
+    async fn connect(client_id: &str) -> Result<Self, BoxedError> {
+        let mut stream = None;
+        for path in socket_paths() {
+            if let Ok(connected) = UnixStream::connect(&path).await {
+                stream = Some(connected);
+                break;
+            }
+        }
+
+        let mut client = Client {
+            stream: stream.ok_or("Discord is not running")?,
+        };
+        client
+            .send(HANDSHAKE, json!({ "v": 1, "client_id": client_id }))
+            .await?;
+
+        Ok(client)
+    }
+
+    /// Send a message and wait for the reply, which is an error if Discord didn't like it
+    async fn send(&mut self, opcode: u32, payload: Value) -> Result<(), BoxedError> {
+        let payload = payload.to_string();
+        let mut message = Vec::with_capacity(8 + payload.len());
+        message.extend_from_slice(&opcode.to_le_bytes());
+        message.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        message.extend_from_slice(payload.as_bytes());
+        self.stream.write_all(&message).await?;
+
+        // Every message starts with its opcode and length, both as 32 bit little endian
+        let mut header = [0; 8];
+        self.stream.read_exact(&mut header).await?;
+        let length = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+        let mut reply = vec![0; length as usize];
+        self.stream.read_exact(&mut reply).await?;
+
+        let reply: Value = serde_json::from_slice(&reply)?;
+        if reply["evt"] == "ERROR" {
+            return Err(
+                format!("Discord refused the presence: {}", reply["data"]["message"]).into(),
+            );
+        }
+
+        Ok(())
+    }
+
+    async fn set_activity(&mut self, presence: Option<&Presence>) -> Result<(), BoxedError> {
+        self.send(
+            FRAME,
+            json!({
+                "cmd": "SET_ACTIVITY",
+                "args": {
+                    "pid": std::process::id(),
+                    "activity": presence.map(Presence::activity),
+                },
+                "nonce": SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos().to_string(),
+            }),
+        )
+        .await
+    }
+}
+
+/// Publish the presence whenever it changes, connecting to Discord when it is needed
+pub async fn publish(client_id: String, mut presences: watch::Receiver<Option<Presence>>) {
+    let mut client: Option<Client> = None;
+
+    while presences.changed().await.is_ok() {
+        let presence = presences.borrow_and_update().clone();
+
+        // Discord may have been started or restarted since the last time, so connect again if needed
+        if client.is_none() {
+            match Client::connect(&client_id).await {
+                Ok(connected) => client = Some(connected),
+                Err(err) => {
+                    debug!(event = "discord", "Failed to connect to Discord: {}", err);
+                    continue;
+                }
+            }
+        }
+
+        if let Some(connected) = &mut client {
+            if let Err(err) = connected.set_activity(presence.as_ref()).await {
+                warn!(
+                    event = "discord",
+                    "Failed to set the presence on Discord: {}", err
+                );
+                client = None;
+            }
+        }
+    }
+}
@@ -0,0 +1,413 @@
+use crate::options::HELP;
+
+/// The shells that completions can be generated for
+#[derive(Clone, Copy)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+impl std::str::FromStr for Shell {
+    type Err = String;
+
+    fn from_str(shell: &str) -> Result<Self, Self::Err> {
+        match shell {
+            "bash" => Ok(Shell::Bash),
+            "zsh" => Ok(Shell::Zsh),
+            "fish" => Ok(Shell::Fish),
+            _ => Err(format!(
+                "unknown shell '{}', expected bash, zsh or fish",
+                shell
+            )),
+        }
+    }
+}
+
+/// A command or an option as the help describes it
+struct Entry {
+    name: String,
+    value: Option<String>,
+    description: String,
+    default: Option<String>,
+}
+
+/// The column where descriptions start in the help
+const DESCRIPTION_COLUMN: usize = 24;
+
+/// Read the entries of a section of the help, such as COMMANDS or OPTIONS
+fn section(help: &str, name: &str) -> Vec<Entry> {
+    let mut entries: Vec<Entry> = Vec::new();
+
+    let lines = help
+        .lines()
+        .skip_while(|line| *line != format!("{}:", name))
+        .skip(1)
+        .take_while(|line| line.starts_with(' '));
+
+    for line in lines {
+        // Long descriptions go on at the description column of the next line
+        // A character wider than a byte before the column can't be the start of a continued description
+        let continued = line
+            .get(..DESCRIPTION_COLUMN)
+            .is_some_and(|start| start.trim().is_empty());
+        if continued && line.len() > DESCRIPTION_COLUMN {
+            if let Some(entry) = entries.last_mut() {
+                entry.description.push(' ');
+                entry.description.push_str(line.trim());
+            }
+            continue;
+        }
+
+        let (line, default) = match line.split_once("<Default: ") {
+            Some((line, default)) => (line, default.strip_suffix('>').map(String::from)),
+            None => (line, None),
+        };
+
+        // The name comes first, followed by its value if it takes one, such as FILE or [NAME]
+        let mut words = line.split_whitespace().peekable();
+        let Some(name) = words.next() else {
+            continue;
+        };
+        let value = words
+            .next_if(|word| {
+                word.trim_matches(['[', ']'])
+                    .chars()
+                    .all(|c| c.is_ascii_uppercase())
+            })
+            .map(String::from);
+
+        entries.push(Entry {
+            name: name.to_string(),
+            value,
+            description: words.collect::<Vec<_>>().join(" "),
+            default: default.filter(|default| default != "None" && default != "False"),
+        });
+    }
+
+    entries
+}
+
+/// The values of options and commands that can be completed, the same that they accept
+fn choices(name: &str) -> Option<&'static [&'static str]> {
+    match name {
+        "--output" => Some(&["waybar", "plain", "i3bar", "yambar", "xsetroot", "tmux"]),
+        "--backend" => Some(&["mpris", "mpd", "cmus"]),
+        "--escape" => Some(&["none", "ampersand", "pango", "json"]),
+        "--rating-style" => Some(&["stars", "number"]),
+        "--log-level" => Some(&["error", "warn", "info", "debug", "trace"]),
+        "loop" => Some(&["None", "Track", "Playlist"]),
+        "control" => Some(&[
+            "refresh",
+            "play-pause",
+            "next",
+            "previous",
+            "stop",
+            "switch",
+        ]),
+        "completions" => Some(&["bash", "zsh", "fish"]),
+        _ => None,
+    }
+}
+
+/// Check if the value of an option is a path, so that files are completed for it
+fn takes_path(entry: &Entry) -> bool {
+    entry.value.as_deref() == Some("PATH")
+}
+
+/// Generate completions for a shell, from the same help that `--help` prints
+pub fn completions(shell: Shell) -> String {
+    let commands = section(HELP, "COMMANDS");
+    let options = section(HELP, "OPTIONS");
+
+    match shell {
+        Shell::Bash => bash(&commands, &options),
+        Shell::Zsh => zsh(&commands, &options),
+        Shell::Fish => fish(&commands, &options),
+    }
+}
+
+fn bash(commands: &[Entry], options: &[Entry]) -> String {
+    let names = |entries: &[Entry]| {
+        entries
+            .iter()
+            .map(|entry| entry.name.as_str())
+            .collect::<Vec<_>>()
+            .join(" ")
+    };
+
+    let mut cases = String::new();
+    for entry in commands.iter().chain(options) {
+        if let Some(choices) = choices(&entry.name) {
+            cases.push_str(&format!(
+                "        {})\n            COMPREPLY=($(compgen -W \"{}\" -- \"$cur\"))\n            return\n            ;;\n",
+                entry.name,
+                choices.join(" ")
+            ));
+        } else if takes_path(entry) {
+            cases.push_str(&format!(
+                "        {})\n            COMPREPLY=($(compgen -f -- \"$cur\"))\n            return\n            ;;\n",
+                entry.name
+            ));
+        } else if entry.name.starts_with("--") && entry.value.is_some() {
+            // Anything can be given, so there is nothing to complete
+            cases.push_str(&format!(
+                "        {})\n            return\n            ;;\n",
+                entry.name
+            ));
+        }
+    }
+
+    format!(
+        r#"_lizzy() {{
+    local cur="${{COMP_WORDS[COMP_CWORD]}}"
+    local prev="${{COMP_WORDS[COMP_CWORD-1]}}"
+
+    case "$prev" in
+{cases}    esac
+
+    if [[ "$cur" == -* ]]; then
+        COMPREPLY=($(compgen -W "-h --help {options}" -- "$cur"))
+    else
+        COMPREPLY=($(compgen -W "{commands}" -- "$cur"))
+    fi
+}}
+
+complete -F _lizzy lizzy
+"#,
+        cases = cases,
+        options = names(options),
+        commands = names(commands),
+    )
+}
+
+fn zsh(commands: &[Entry], options: &[Entry]) -> String {
+    // Descriptions go in single quotes after a colon, and those of options also between brackets
+    let quote = |text: &str| text.replace('\'', "'\\''").replace(':', "\\:");
+    let escape = |text: &str| quote(text).replace('[', "\\[").replace(']', "\\]");
+
+    let commands_list: String = commands
+        .iter()
+        .map(|entry| format!("        '{}:{}'\n", entry.name, quote(&entry.description)))
+        .collect();
+
+    let arguments: String = commands
+        .iter()
+        .filter_map(|entry| {
+            let choices = choices(&entry.name)?;
+            Some(format!(
+                "        {}) _values '{}' {} ;;\n",
+                entry.name,
+                entry
+                    .value
+                    .as_deref()?
+                    .trim_matches(['[', ']'])
+                    .to_lowercase(),
+                choices.join(" ")
+            ))
+        })
+        .collect();
+
+    let specs: String = options
+        .iter()
+        .map(|entry| {
+            let action = match (entry.value.as_deref(), choices(&entry.name)) {
+                (Some(value), Some(choices)) => format!(":{}:({})", value, choices.join(" ")),
+                (Some(value), None) if takes_path(entry) => format!(":{}:_files", value),
+                (Some(value), None) => format!(":{}: ", value),
+                (None, _) => String::new(),
+            };
+            format!(
+                "    '{}[{}]{}' \\\n",
+                entry.name,
+                escape(&entry.description),
+                action
+            )
+        })
+        .collect();
+
+    format!(
+        r#"#compdef lizzy
+
+_lizzy_commands() {{
+    local -a commands
+    commands=(
+{commands_list}    )
+    _describe 'command' commands
+}}
+
+_lizzy_argument() {{
+    case $line[1] in
+{arguments}    esac
+}}
+
+_arguments \
+    '(-h --help)'{{-h,--help}}'[Prints help information]' \
+{specs}    '1: :_lizzy_commands' \
+    '*:: :_lizzy_argument'
+"#,
+        commands_list = commands_list,
+        arguments = arguments,
+        specs = specs,
+    )
+}
+
+fn fish(commands: &[Entry], options: &[Entry]) -> String {
+    let quote = |text: &str| format!("'{}'", text.replace('\\', "\\\\").replace('\'', "\\'"));
+
+    let mut lines = vec![
+        String::from("complete -c lizzy -f"),
+        String::from("complete -c lizzy -s h -l help -d 'Prints help information'"),
+    ];
+
+    for entry in commands {
+        lines.push(format!(
+            "complete -c lizzy -n __fish_use_subcommand -a {} -d {}",
+            entry.name,
+            quote(&entry.description)
+        ));
+        if let Some(choices) = choices(&entry.name) {
+            lines.push(format!(
+                "complete -c lizzy -n '__fish_seen_subcommand_from {}' -a {}",
+                entry.name,
+                quote(&choices.join(" "))
+            ));
+        }
+    }
+
+    for entry in options {
+        let mut line = format!(
+            "complete -c lizzy -l {}",
+            entry.name.trim_start_matches('-')
+        );
+        if entry.value.is_some() {
+            line.push_str(" -r");
+        }
+        if let Some(choices) = choices(&entry.name) {
+            line.push_str(&format!(" -a {}", quote(&choices.join(" "))));
+        } else if takes_path(entry) {
+            line.push_str(" -F");
+        }
+        line.push_str(&format!(" -d {}", quote(&entry.description)));
+        lines.push(line);
+    }
+
+    lines.join("\n") + "\n"
+}
+
+/// Escape text for roff, where backslashes, dashes and dots at the start of a line mean something
+fn roff(text: &str) -> String {
+    let escaped = text.replace('\\', "\\e").replace('-', "\\-");
+    match escaped.starts_with(['.', '\'']) {
+        true => format!("\\&{}", escaped),
+        false => escaped,
+    }
+}
+
+/// Generate the man page, from the same help that `--help` prints
+pub fn man() -> String {
+    let mut page = format!(
+        r#".TH LIZZY 1 "" "lizzy {version}" "User Commands"
+.SH NAME
+lizzy \- show what the mediaplayers play in Waybar and other bars
+.SH SYNOPSIS
+.B lizzy
+[\fICOMMAND\fR] [\fIOPTIONS\fR]
+.SH DESCRIPTION
+lizzy follows mediaplayers over MPRIS, or MPD and cmus directly, and prints what they play whenever it changes,
+formatted for Waybar, i3bar, yambar and other bars.
+It can also pause the mediaplayer when other media plays, and control it with commands that run once.
+"#,
+        version = env!("CARGO_PKG_VERSION")
+    );
+
+    for (title, entries) in [
+        ("COMMANDS", section(HELP, "COMMANDS")),
+        ("OPTIONS", section(HELP, "OPTIONS")),
+    ] {
+        page.push_str(&format!(".SH {}\n", title));
+        for entry in entries {
+            page.push_str(&format!(".TP\n\\fB{}\\fR", roff(&entry.name)));
+            if let Some(value) = &entry.value {
+                page.push_str(&format!(" \\fI{}\\fR", roff(value)));
+            }
+            page.push('\n');
+            page.push_str(&roff(&entry.description));
+            if let Some(default) = &entry.default {
+                page.push_str(&format!(" (default: {})", roff(default)));
+            }
+            page.push('\n');
+        }
+    }
+
+    page.push_str(
+        r#".TP
+\fB\-h\fR, \fB\-\-help\fR
+Prints help information
+.SH FILES
+.TP
+\fI$XDG_CONFIG_HOME/lizzy/config.toml\fR
+Replace rules and the settings of ListenBrainz and MQTT, see the README.
+.SH SEE ALSO
+https://github.com/stefur/lizzy
+"#,
+    );
+
+    page
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn completes_every_option_of_the_help() {
+        let bash = completions(Shell::Bash);
+        let zsh = completions(Shell::Zsh);
+        let fish = completions(Shell::Fish);
+
+        // Read the options straight from the help, so that one the sections leave out is noticed
+        let flags: Vec<&str> = HELP
+            .lines()
+            .skip_while(|line| *line != "OPTIONS:")
+            .skip(1)
+            .take_while(|line| line.starts_with(' '))
+            .filter_map(|line| line.split_whitespace().next())
+            .filter(|word| word.starts_with("--"))
+            .collect();
+        assert!(flags.contains(&"--output"));
+
+        for flag in flags {
+            assert!(
+                bash.split_whitespace()
+                    .any(|word| word.trim_matches('"') == flag),
+                "{} is missing from bash",
+                flag
+            );
+            assert!(
+                zsh.contains(&format!("'{}[", flag)),
+                "{} is missing from zsh",
+                flag
+            );
+            assert!(
+                fish.contains(&format!("-l {} ", flag.trim_start_matches('-'))),
+                "{} is missing from fish",
+                flag
+            );
+        }
+    }
+
+    #[test]
+    fn reads_descriptions_after_wide_characters() {
+        let help = "OPTIONS:\n  --ellipsis STRING     Marks … that the output was cut <Default: \"…\">\n  --añññññññññññññ TEXT Not cut in the middle of a character\n                        and continued\n";
+        let entries = section(help, "OPTIONS");
+
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].default.as_deref(), Some("\"…\""));
+        assert_eq!(entries[1].name, "--añññññññññññññ");
+        assert_eq!(
+            entries[1].description,
+            "Not cut in the middle of a character and continued"
+        );
+    }
+}
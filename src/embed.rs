@@ -0,0 +1,96 @@
+use crate::follow::follow;
+use crate::media::Media;
+use crate::options::Arguments;
+use crate::BoxedError;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tracing::error;
+use zbus::Connection;
+
+/// A change of what is displayed, as given to applications that embed lizzy
+pub enum MediaUpdate {
+    /// The media changed, such as another track, its metadata or the playback status
+    Changed(Box<Media>),
+    /// Nothing is displayed anymore, since the mediaplayer stopped or closed
+    Cleared,
+}
+
+/// The updates of the media that lizzy would display, for bars and other applications written in Rust
+///
+/// Created with [`updates`], and implements [`Stream`] as well as having a [`MediaStream::next`] of its own.
+/// Dropping it stops lizzy.
+///
+/// [`Stream`]: zbus::export::futures_core::Stream
+pub struct MediaStream {
+    receiver: mpsc::Receiver<MediaUpdate>,
+    task: tokio::task::JoinHandle<()>,
+}
+
+impl MediaStream {
+    /// Wait for the next update, or none once lizzy has stopped for good
+    pub async fn next(&mut self) -> Option<MediaUpdate> {
+        self.receiver.recv().await
+    }
+}
+
+impl zbus::export::futures_core::Stream for MediaStream {
+    type Item = MediaUpdate;
+
+    fn poll_next(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Option<MediaUpdate>> {
+        self.receiver.poll_recv(cx)
+    }
+}
+
+impl Drop for MediaStream {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Follow the mediaplayers on the session bus the way lizzy does, but get the media rather than any output
+///
+/// The options are the same as those on the command line, see [`options::parse_from`], and decide which
+/// mediaplayer is followed along with everything else lizzy does on the side, such as autotoggle.
+/// Options about the output itself, such as the format, `--scroll` or `--all-players`, make no difference,
+/// so that everything comes as updates of the one mediaplayer followed. The same media may come more than
+/// once, since mediaplayers tend to repeat themselves.
+///
+/// ```no_run
+/// # async fn example() -> Result<(), lizzy::BoxedError> {
+/// use lizzy::options;
+/// use lizzy::MediaUpdate;
+///
+/// let options = options::parse_from(vec!["--mediaplayer".into(), "spotify".into()])?;
+/// let mut updates = lizzy::updates(options).await?;
+///
+/// while let Some(update) = updates.next().await {
+///     match update {
+///         MediaUpdate::Changed(media) => println!("{:?}", media.metadata.title),
+///         MediaUpdate::Cleared => println!("Nothing is playing"),
+///     }
+/// }
+/// # Ok(())
+/// # }
+/// ```
+///
+/// [`options::parse_from`]: crate::options::parse_from
+pub async fn updates(mut options: Arguments) -> Result<MediaStream, BoxedError> {
+    // Scrolling and the array of all mediaplayers only shape the printed output, which is left to the application
+    options.scroll = false;
+    options.all_players = false;
+
+    let options = Arc::new(options);
+    let connection = Connection::session().await?;
+    let (sender, receiver) = mpsc::channel(10);
+
+    let task = tokio::spawn(async move {
+        if let Err(err) = follow(connection, options, Some(sender)).await {
+            error!("Failed to follow the mediaplayers: {}", err);
+        }
+    });
+
+    Ok(MediaStream { receiver, task })
+}
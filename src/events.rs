@@ -0,0 +1,57 @@
+use crate::media;
+use crate::{accept_failed, BoxedError};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::watch;
+use tracing::debug;
+
+/// Accept clients on the event socket, so that any number of widgets can share one lizzy
+pub async fn serve(
+    listener: UnixListener,
+    now_playing: watch::Receiver<Option<String>>,
+) -> Result<(), BoxedError> {
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(err) => {
+                accept_failed("event socket", err).await;
+                continue;
+            }
+        };
+        let now_playing = now_playing.clone();
+
+        tokio::spawn(async move {
+            if let Err(err) = stream_events(stream, now_playing).await {
+                debug!("Event client went away: {}", err);
+            }
+        });
+    }
+}
+
+/// Send what is playing as a line of JSON, and again whenever it changes, until the client goes away
+async fn stream_events(
+    stream: UnixStream,
+    mut now_playing: watch::Receiver<Option<String>>,
+) -> Result<(), BoxedError> {
+    let (mut reader, mut writer) = stream.into_split();
+    let mut buffer = [0; 64];
+
+    let line = media::latest(&mut now_playing);
+    writer.write_all(format!("{}\n", line).as_bytes()).await?;
+
+    loop {
+        tokio::select! {
+            changed = now_playing.changed() => {
+                changed?;
+                let line = media::latest(&mut now_playing);
+                writer.write_all(format!("{}\n", line).as_bytes()).await?;
+            }
+            // Clients only listen, so reading is only to notice them closing the connection
+            read = reader.read(&mut buffer) => {
+                if read? == 0 {
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
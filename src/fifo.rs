@@ -0,0 +1,73 @@
+use crate::BoxedError;
+use std::os::unix::fs::FileTypeExt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::fs::File;
+use tokio::io::{AsyncWriteExt, Interest};
+use tokio::net::unix::pipe;
+use tokio::process::Command;
+use tokio::sync::watch;
+use tracing::{debug, error};
+
+/// Create the FIFO, or make sure that what is already there is one
+pub async fn create(path: &Path) -> Result<(), BoxedError> {
+    match tokio::fs::metadata(path).await {
+        Ok(metadata) if metadata.file_type().is_fifo() => Ok(()),
+        Ok(_) => Err(format!("{} exists and is not a FIFO", path.display()).into()),
+        Err(_) => {
+            let created = Command::new("mkfifo")
+                .arg(path)
+                .status()
+                .await
+                .is_ok_and(|status| status.success());
+            if !created {
+                return Err(format!("could not create a FIFO at {}", path.display()).into());
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Write every line of output to the FIFO, starting over with the header and the latest line for every reader
+pub async fn write(path: PathBuf, header: Vec<String>, mut lines: watch::Receiver<Option<String>>) {
+    loop {
+        // Opening waits until something opens the FIFO for reading
+        let fifo = match File::options().write(true).open(&path).await {
+            Ok(fifo) => pipe::Sender::from_file(fifo.into_std().await),
+            Err(err) => Err(err),
+        };
+        let mut fifo = match fifo {
+            Ok(fifo) => fifo,
+            Err(err) => {
+                error!("Failed to open the FIFO {}: {}", path.display(), err);
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                continue;
+            }
+        };
+
+        let mut pending = header.clone();
+        pending.extend(lines.borrow_and_update().clone());
+
+        // Once the reader goes away the FIFO is opened again for the next one, which then gets the latest line at once
+        loop {
+            let written: String = pending.drain(..).map(|line| line + "\n").collect();
+            if let Err(err) = fifo.write_all(written.as_bytes()).await {
+                debug!("Failed to write to the FIFO: {}", err);
+                break;
+            }
+
+            tokio::select! {
+                changed = lines.changed() => {
+                    if changed.is_err() {
+                        return;
+                    }
+                    pending.extend(lines.borrow_and_update().clone());
+                }
+                _ = fifo.ready(Interest::ERROR) => {
+                    debug!("Reader of the FIFO went away");
+                    break;
+                }
+            }
+        }
+    }
+}
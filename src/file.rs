@@ -0,0 +1,44 @@
+use crate::media::Media;
+use crate::options::Arguments;
+use std::path::Path;
+use std::sync::Mutex;
+use tracing::error;
+
+/// The text of the output file, with its own format if one is given and without any escaping
+fn text(options: &Arguments, media: Option<&Media>) -> String {
+    media
+        .and_then(|media| {
+            let format = options
+                .output_file_format
+                .clone()
+                .unwrap_or_else(|| media.format(options));
+            media.render(&format, options)
+        })
+        .or_else(|| options.idle_text.clone())
+        .unwrap_or_default()
+}
+
+/// Write what is playing to the output file, unless it already says the same
+pub async fn write(
+    path: &Path,
+    options: &Arguments,
+    written: &Mutex<Option<String>>,
+    media: Option<&Media>,
+) {
+    let text = text(options, media);
+    if written.lock().expect("Written lock poisoned").as_ref() == Some(&text) {
+        return;
+    }
+
+    // Write next to the file and move it in place, so that whatever watches it never reads half of it
+    let partial = path.with_extension("part");
+    let result = match tokio::fs::write(&partial, &text).await {
+        Ok(()) => tokio::fs::rename(&partial, path).await,
+        Err(err) => Err(err),
+    };
+
+    match result {
+        Ok(()) => *written.lock().expect("Written lock poisoned") = Some(text),
+        Err(err) => error!("Failed to write the output to {}: {}", path.display(), err),
+    }
+}
@@ -0,0 +1,398 @@
+use crate::control::{self, ControlMessage};
+use crate::embed::MediaUpdate;
+use crate::media::{self, Sink};
+use crate::options::{Arguments, Backend};
+use crate::streams::{name_owner_changed_stream, property_changes_stream};
+use crate::BoxedError;
+use crate::{
+    audio, cmus, config, discord, events, fifo, logind, mpd, mqtt, output, output_all, refresh,
+    reload, resolve_mediaplayer, server, service, signals, systemd, tick, waybar, State,
+};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::net::TcpListener;
+use tokio::sync::{mpsc, watch};
+use tracing::{error, info, warn};
+use zbus::Connection;
+
+/// How long to wait before trying to reconnect to the session bus, doubled after each failed attempt
+const RECONNECT_BACKOFF_MIN: Duration = Duration::from_secs(1);
+const RECONNECT_BACKOFF_MAX: Duration = Duration::from_secs(30);
+
+/// Set up everything on a connection to the session bus and handle the streams until the connection is lost
+async fn run_connection(
+    connection: &Connection,
+    options: &Arguments,
+    state: &Arc<State>,
+    control_messages: &mut Option<mpsc::Receiver<ControlMessage>>,
+    session_events: &mut Option<mpsc::Receiver<logind::Event>>,
+    microphone_changes: &mut Option<mpsc::Receiver<bool>>,
+    reconnected: bool,
+) -> Result<(), BoxedError> {
+    // Optionally publish what is displayed on the session bus
+    if options.dbus_service {
+        service::register(connection).await?;
+    }
+
+    // After reconnecting the output is brought up to date, since anything could have happened in between
+    if options.all_players {
+        output_all(connection, options, state).await?;
+    } else if reconnected {
+        match resolve_mediaplayer(connection, options, state).await? {
+            Some(busname) => refresh(connection, options, state, &busname).await?,
+            None => output(connection, options, state, None).await,
+        }
+    }
+
+    // Set up streams to handle properties as well as opening/closing mediaplayers, where either one ending means starting over
+    tokio::select! {
+        result = property_changes_stream(
+            connection.clone(),
+            options,
+            state.clone(),
+            control_messages,
+            session_events,
+            microphone_changes
+        ) => result,
+        result = name_owner_changed_stream(connection.clone(), options, state.clone()) => result,
+    }
+}
+
+/// Output what MPD plays until the connection to it is lost
+async fn follow_mpd(
+    connection: &Connection,
+    options: &Arguments,
+    state: &State,
+    backoff: &mut Duration,
+) -> Result<(), BoxedError> {
+    let mut client = mpd::Client::connect().await?;
+    *backoff = RECONNECT_BACKOFF_MIN;
+
+    // Ping the watchdog of systemd while waiting, so that it notices if lizzy ever hangs
+    let mut watchdog_ticker = systemd::watchdog_interval().map(tokio::time::interval);
+
+    loop {
+        let media = client.media(options).await?;
+        output(connection, options, state, media.as_ref()).await;
+
+        let idle = client.idle();
+        tokio::pin!(idle);
+        loop {
+            tokio::select! {
+                result = &mut idle => break result?,
+                _ = tick(&mut watchdog_ticker) => {
+                    if let Err(err) = systemd::notify("WATCHDOG=1") {
+                        warn!("Failed to ping the systemd watchdog: {}", err);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Output what MPD plays, reconnecting whenever the connection to it is lost
+async fn run_mpd(
+    connection: &Connection,
+    options: &Arguments,
+    state: &State,
+) -> Result<(), BoxedError> {
+    let mut backoff = RECONNECT_BACKOFF_MIN;
+
+    if let Err(err) = systemd::notify("READY=1") {
+        warn!("Failed to notify systemd: {}", err);
+    }
+
+    loop {
+        if let Err(err) = follow_mpd(connection, options, state, &mut backoff).await {
+            warn!(
+                "MPD is unavailable, retrying in {} seconds: {}",
+                backoff.as_secs(),
+                err
+            );
+        }
+
+        // Nothing is known to be playing until MPD is back
+        output(connection, options, state, None).await;
+        if let Err(err) = systemd::notify("WATCHDOG=1") {
+            warn!("Failed to ping the systemd watchdog: {}", err);
+        }
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(RECONNECT_BACKOFF_MAX);
+    }
+}
+
+/// How often to ask cmus for its status, since it can't tell when it changes
+const CMUS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Output what cmus plays, asking it on an interval
+async fn run_cmus(
+    connection: &Connection,
+    options: &Arguments,
+    state: &State,
+) -> Result<(), BoxedError> {
+    let mut ticker =
+        tokio::time::interval(options.interval.map_or(CMUS_INTERVAL, Duration::from_secs));
+    let mut available = true;
+
+    if let Err(err) = systemd::notify("READY=1") {
+        warn!("Failed to notify systemd: {}", err);
+    }
+
+    loop {
+        ticker.tick().await;
+
+        let media = match cmus::media(options).await {
+            Ok(media) => {
+                available = true;
+                media
+            }
+            Err(err) => {
+                // cmus not running is common, so only tell once until it is back
+                if available {
+                    warn!("cmus is unavailable: {}", err);
+                }
+                available = false;
+                None
+            }
+        };
+        output(connection, options, state, media.as_ref()).await;
+
+        if let Err(err) = systemd::notify("WATCHDOG=1") {
+            warn!("Failed to ping the systemd watchdog: {}", err);
+        }
+    }
+}
+
+/// Keep the output up to date, or pass the media on to the stream of updates when there is one
+pub async fn follow(
+    connection: Connection,
+    options: Arc<Arguments>,
+    updates: Option<mpsc::Sender<MediaUpdate>>,
+) -> Result<(), BoxedError> {
+    let embedded = updates.is_some();
+
+    // Optionally publish what is playing as the Rich Presence of Discord, which happens on its own
+    let presence = options.discord_client_id.as_ref().map(|client_id| {
+        let (sender, receiver) = watch::channel(None);
+        tokio::spawn(discord::publish(client_id.clone(), receiver));
+        sender
+    });
+
+    // Optionally publish what is playing to an MQTT broker for home automation, which happens on its own too
+    let mqtt = options.config().mqtt.as_ref().map(|mqtt| {
+        let (sender, receiver) = watch::channel(None);
+        tokio::spawn(mqtt::publish(mqtt.clone(), receiver));
+        sender
+    });
+
+    // Optionally signal Waybar when the output changes, for modules that run their exec on a signal
+    let waybar = options.signal.map(|number| {
+        let (sender, receiver) = watch::channel(None);
+        tokio::spawn(waybar::signal(number, receiver));
+        sender
+    });
+
+    // What is playing is shared with the HTTP server and the event socket, whichever are enabled
+    let now_playing =
+        (options.serve.is_some() || options.event_socket.is_some()).then(|| watch::channel(None));
+
+    // Optionally serve what is playing over HTTP and WebSocket, binding first so that a taken address is an error
+    if let (Some(address), Some((_, receiver))) = (options.serve, &now_playing) {
+        let listener = TcpListener::bind(address)
+            .await
+            .map_err(|err| format!("could not listen on {}: {}", address, err))?;
+        let receiver = receiver.clone();
+        let origins = options.serve_origin.clone();
+        tokio::spawn(async move {
+            if let Err(err) = server::serve(listener, receiver, origins).await {
+                error!("HTTP server failed: {}", err);
+            }
+        });
+    }
+
+    // Optionally stream what is playing as lines of JSON to every client of the event socket
+    if let (Some(path), Some((_, receiver))) = (&options.event_socket, &now_playing) {
+        let listener = control::bind(path).await?;
+        let receiver = receiver.clone();
+        tokio::spawn(async move {
+            if let Err(err) = events::serve(listener, receiver).await {
+                error!("Event socket failed: {}", err);
+            }
+        });
+    }
+
+    // Optionally write the output to a FIFO instead of stdout, for bars and scripts that read from a pipe
+    let sink = match (&options.fifo, embedded) {
+        (Some(path), false) => {
+            fifo::create(path).await?;
+            let header = match options.all_players {
+                true => Vec::new(),
+                false => media::header_lines(&options),
+            };
+            let (sender, receiver) = watch::channel(None);
+            tokio::spawn(fifo::write(path.clone(), header, receiver));
+            Sink::to_fifo(sender)
+        }
+        _ => Sink::default(),
+    };
+
+    // Both streams need to know which mediaplayers have been active when following
+    let state: Arc<State> = Arc::new(State {
+        sink,
+        presence,
+        mqtt,
+        waybar,
+        now_playing: now_playing.map(|(sender, _)| sender),
+        updates,
+        ..State::default()
+    });
+
+    // Read the config file again whenever it changes, so that changing the format or the mediaplayers needs no restart
+    if let Some(path) = config::path(options.config_path.as_deref()).filter(|path| path.exists()) {
+        let (options, state) = (options.clone(), state.clone());
+        tokio::spawn(async move {
+            if let Err(err) = reload::watch(&options, &path, &state.config_reloaded).await {
+                warn!("Failed to watch the config file for changes: {}", err);
+            }
+        });
+    }
+
+    // Requests are handled by the property stream, whether they come from the control socket or from signals
+    let (control_sender, receiver) = mpsc::channel(10);
+    let mut control_messages = (options.control || !embedded).then_some(receiver);
+
+    // Optionally listen for requests on the control socket
+    if options.control {
+        let listener = control::bind(&control::socket_path(&options)?).await?;
+        let sender = control_sender.clone();
+        tokio::spawn(async move {
+            if let Err(err) = control::serve(listener, sender).await {
+                error!("Control socket failed: {}", err);
+            }
+        });
+    }
+
+    // SIGUSR1 prints the output again and SIGUSR2 toggles playback, which is left to the application when embedded
+    if !embedded {
+        tokio::spawn(async move {
+            if let Err(err) = signals::forward(control_sender).await {
+                error!("Failed to handle signals: {}", err);
+            }
+        });
+    }
+
+    // Optionally watch the session on the system bus, so that playback can be paused when it locks or suspends
+    let mut session_events = if options.pause_on_lock || options.pause_on_suspend {
+        let (sender, receiver) = mpsc::channel(10);
+        let options = options.clone();
+        tokio::spawn(async move {
+            if let Err(err) = logind::watch(&options, sender).await {
+                error!("Failed to watch the session: {}", err);
+            }
+        });
+        Some(receiver)
+    } else {
+        None
+    };
+
+    // Optionally watch the audio server for recording, so that playback can be paused during calls
+    let mut microphone_changes = if options.pause_on_microphone {
+        let (sender, receiver) = mpsc::channel(10);
+        tokio::spawn(async move {
+            if let Err(err) = audio::watch_microphone(sender).await {
+                error!("Failed to watch the microphone: {}", err);
+            }
+        });
+        Some(receiver)
+    } else {
+        None
+    };
+
+    // Some kinds of output have to start with a header, which doesn't go for the array of all mediaplayers
+    if !options.all_players && !embedded {
+        state.sink.header(&options);
+
+        // Show the idle text until something plays
+        if options.idle_text.is_some() {
+            state.sink.clear(&options);
+        }
+    }
+
+    // MPD and cmus are asked directly instead of through MPRIS, so none of the streams are needed
+    match options.backend {
+        Backend::Mpris => {}
+        Backend::Mpd => return run_mpd(&connection, &options, &state).await,
+        Backend::Cmus => return run_cmus(&connection, &options, &state).await,
+    }
+
+    let mut connection = Some(connection);
+    let mut backoff = RECONNECT_BACKOFF_MIN;
+
+    // The streams end when the connection to the session bus is lost, so keep reconnecting until it is back
+    loop {
+        let (connection, reconnected) = match connection.take() {
+            Some(connection) => (connection, false),
+            None => match Connection::session().await {
+                Ok(connection) => (connection, true),
+                Err(err) => {
+                    warn!(
+                        "Failed to connect to the session bus, retrying in {} seconds: {}",
+                        backoff.as_secs(),
+                        err
+                    );
+                    wait_to_reconnect(&mut backoff).await;
+                    continue;
+                }
+            },
+        };
+
+        if reconnected {
+            info!("Reconnected to the session bus");
+        }
+
+        let connected = Instant::now();
+        let result = run_connection(
+            &connection,
+            &options,
+            &state,
+            &mut control_messages,
+            &mut session_events,
+            &mut microphone_changes,
+            reconnected,
+        )
+        .await;
+
+        // Only streams that kept running for a while make a working connection, since whatever fails
+        // right after connecting, such as another lizzy owning the service name, will likely fail again
+        if connected.elapsed() >= RECONNECT_BACKOFF_MAX {
+            backoff = RECONNECT_BACKOFF_MIN;
+        }
+
+        match result {
+            Err(err) => warn!(
+                "Lost the session bus, retrying in {} seconds: {}",
+                backoff.as_secs(),
+                err
+            ),
+            Ok(()) => warn!(
+                "Lost the session bus, reconnecting in {} seconds",
+                backoff.as_secs()
+            ),
+        }
+
+        // Anything known about the mediaplayers on the old connection is useless on a new one
+        state.forget_bus();
+
+        wait_to_reconnect(&mut backoff).await;
+    }
+}
+
+/// Wait before reconnecting to the session bus, doubling the wait for the next time
+async fn wait_to_reconnect(backoff: &mut Duration) {
+    // Keep the watchdog of systemd at ease, since waiting for the session bus is not a hang
+    if let Err(err) = systemd::notify("WATCHDOG=1") {
+        warn!("Failed to ping the systemd watchdog: {}", err);
+    }
+    tokio::time::sleep(*backoff).await;
+    *backoff = (*backoff * 2).min(RECONNECT_BACKOFF_MAX);
+}
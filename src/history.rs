@@ -0,0 +1,154 @@
+use crate::media::Media;
+use crate::play::Plays;
+use crate::xdg;
+use crate::BoxedError;
+use serde_json::json;
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tracing::{debug, error};
+
+/// Plays are keyed by the trackid and when they started, so that the same play is never recorded twice
+const SCHEMA: &str = "CREATE TABLE IF NOT EXISTS history (
+    trackid TEXT NOT NULL,
+    artist TEXT,
+    title TEXT,
+    album TEXT,
+    player TEXT,
+    started_at INTEGER NOT NULL,
+    ended_at INTEGER NOT NULL,
+    played INTEGER NOT NULL,
+    PRIMARY KEY (trackid, started_at)
+);";
+
+/// The play is imported as a row of JSON and only then taken apart, so no value is ever part of the SQL itself
+const INSERT: &str = "INSERT OR IGNORE INTO history SELECT
+    json_extract(row, '$.trackid'),
+    json_extract(row, '$.artist'),
+    json_extract(row, '$.title'),
+    json_extract(row, '$.album'),
+    json_extract(row, '$.player'),
+    json_extract(row, '$.started_at'),
+    json_extract(row, '$.ended_at'),
+    json_extract(row, '$.played')
+FROM temp.play;";
+
+/// What is kept about a track that is being played
+struct Entry {
+    trackid: String,
+    artist: Option<String>,
+    title: Option<String>,
+    album: Option<String>,
+    player: Option<String>,
+}
+
+impl Entry {
+    fn from_media(media: &Media) -> Self {
+        Entry {
+            trackid: media.metadata.trackid.clone().unwrap_or_default(),
+            artist: media.metadata.artist.clone(),
+            title: media.metadata.title.clone(),
+            album: media.metadata.album.clone(),
+            player: media.identity.clone().or_else(|| media.player.clone()),
+        }
+    }
+}
+
+/// The play of the track that is displayed, shared between updates of the output
+#[derive(Default)]
+pub struct History {
+    plays: Plays<Entry>,
+}
+
+/// Keep count of how long the track is played, and record it once the next track comes if it was listened to
+pub fn update(history: &Mutex<History>, media: Option<&Media>) {
+    let mut history = history.lock().expect("History lock poisoned");
+
+    // The previous track is done with, so it is recorded if it was played long enough
+    if let Some(play) = history
+        .plays
+        .update(media, |media| Some(Entry::from_media(media)))
+        .filter(|play| play.listened())
+    {
+        let played = play.played().as_secs();
+        let entry = play.about;
+        record(
+            json!({
+                "trackid": entry.trackid,
+                "artist": entry.artist,
+                "title": entry.title,
+                "album": entry.album,
+                "player": entry.player,
+                "started_at": play.started_at,
+                "ended_at": unix_time(),
+                "played": played,
+            })
+            .to_string(),
+        );
+    }
+}
+
+/// The seconds since the epoch, as the history keeps time
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_secs())
+}
+
+/// The database to record plays in, following the XDG base directories
+fn database_path() -> Option<PathBuf> {
+    Some(xdg::data_home()?.join("lizzy").join("history.db"))
+}
+
+/// Record a play in the background, so that a busy database doesn't hold up the output
+fn record(row: String) {
+    tokio::spawn(async move {
+        match write(&row).await {
+            Ok(()) => debug!(event = "history", "Recorded a play in the history"),
+            Err(err) => error!(event = "history", "Failed to record a play: {}", err),
+        }
+    });
+}
+
+/// Insert the play with sqlite3, creating the database on the first play
+async fn write(row: &str) -> Result<(), BoxedError> {
+    let path = database_path().ok_or("Neither XDG_DATA_HOME nor HOME is set")?;
+    if let Some(dir) = path.parent() {
+        tokio::fs::create_dir_all(dir).await?;
+    }
+
+    // The ASCII mode splits rows and columns on control characters, which JSON always escapes,
+    // so the row arrives whole on the standard input whatever the metadata holds
+    let mut sqlite = Command::new("sqlite3")
+        .args(["-bail", "-cmd", ".timeout 5000"])
+        .arg(&path)
+        .args([
+            SCHEMA,
+            "CREATE TEMP TABLE play (row TEXT);",
+            ".mode ascii",
+            ".import /dev/stdin play",
+            INSERT,
+        ])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|err| format!("could not run sqlite3: {}", err))?;
+
+    if let Some(mut stdin) = sqlite.stdin.take() {
+        stdin.write_all(row.as_bytes()).await?;
+    }
+
+    let output = sqlite.wait_with_output().await?;
+    if !output.status.success() {
+        return Err(String::from_utf8_lossy(&output.stderr)
+            .trim()
+            .to_string()
+            .into());
+    }
+
+    Ok(())
+}
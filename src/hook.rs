@@ -0,0 +1,22 @@
+use crate::media::Media;
+use tokio::process::Command;
+use tracing::error;
+
+/// Spawn the user defined command with the media available as environment variables
+pub fn spawn(command: &str, media: &Media) {
+    let field = |value: &Option<String>| value.clone().unwrap_or_default();
+
+    // The command runs on its own, we don't wait for it to finish
+    if let Err(err) = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .env("LIZZY_ARTIST", field(&media.metadata.artist))
+        .env("LIZZY_TITLE", field(&media.metadata.title))
+        .env("LIZZY_ALBUM", field(&media.metadata.album))
+        .env("LIZZY_STATUS", field(&media.playbackstatus))
+        .env("LIZZY_PLAYER", field(&media.player))
+        .spawn()
+    {
+        error!("Failed to run on-change command: {}", err);
+    }
+}
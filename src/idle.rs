@@ -0,0 +1,49 @@
+use crate::BoxedError;
+use std::sync::Mutex;
+use zbus::Connection;
+
+const DESTINATION: &str = "org.freedesktop.ScreenSaver";
+const PATH: &str = "/org/freedesktop/ScreenSaver";
+
+/// Keep the screen from going idle while playing, and let it go idle again once playback stops
+pub async fn inhibit(
+    connection: &Connection,
+    cookie: &Mutex<Option<u32>>,
+    playing: bool,
+) -> Result<(), BoxedError> {
+    let inhibited = cookie.lock().expect("Idle cookie lock poisoned").is_some();
+
+    match (playing, inhibited) {
+        (true, false) => {
+            let reply = connection
+                .call_method(
+                    Some(DESTINATION),
+                    PATH,
+                    Some(DESTINATION),
+                    "Inhibit",
+                    &("lizzy", "Playing media"),
+                )
+                .await?;
+            *cookie.lock().expect("Idle cookie lock poisoned") =
+                Some(reply.body().deserialize::<u32>()?);
+        }
+        (false, true) => {
+            // Forget the cookie either way, since a screensaver that lost it has nothing to release
+            let Some(previous) = cookie.lock().expect("Idle cookie lock poisoned").take() else {
+                return Ok(());
+            };
+            connection
+                .call_method(
+                    Some(DESTINATION),
+                    PATH,
+                    Some(DESTINATION),
+                    "UnInhibit",
+                    &(previous,),
+                )
+                .await?;
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
@@ -0,0 +1,80 @@
+use crate::xdg;
+use crate::BoxedError;
+use std::path::PathBuf;
+use std::process::Stdio;
+use tokio::process::Command;
+use zbus::fdo::DBusProxy;
+use zbus::names::{BusName, WellKnownName};
+use zbus::Connection;
+
+/// The directories to look for desktop entries in, following the XDG base directories
+fn application_dirs() -> Vec<PathBuf> {
+    let data_dirs = match std::env::var("XDG_DATA_DIRS") {
+        Ok(data_dirs) if !data_dirs.is_empty() => data_dirs,
+        _ => String::from("/usr/local/share:/usr/share"),
+    };
+
+    xdg::data_home()
+        .into_iter()
+        .chain(data_dirs.split(':').map(PathBuf::from))
+        .map(|dir| dir.join("applications"))
+        .collect()
+}
+
+/// Find the command of the desktop entry of a mediaplayer, without the field codes such as %U
+fn desktop_exec(name: &str) -> Option<String> {
+    let contents = application_dirs()
+        .iter()
+        .flat_map(|dir| [name.to_string(), name.to_lowercase()].map(|name| dir.join(name)))
+        .find_map(|path| std::fs::read_to_string(path.with_extension("desktop")).ok())?;
+
+    // Only the main section counts, since actions have commands of their own
+    let exec = contents
+        .lines()
+        .skip_while(|line| line.trim() != "[Desktop Entry]")
+        .skip(1)
+        .take_while(|line| !line.starts_with('['))
+        .find_map(|line| line.strip_prefix("Exec="))?;
+
+    Some(
+        exec.split_whitespace()
+            .filter(|word| !(word.len() == 2 && word.starts_with('%') && *word != "%%"))
+            .map(|word| word.replace("%%", "%"))
+            .collect::<Vec<_>>()
+            .join(" "),
+    )
+}
+
+/// Start a mediaplayer through D-Bus activation if it can be, otherwise from its desktop entry
+pub async fn launch(connection: &Connection, name: &str) -> Result<(), BoxedError> {
+    let dbus_proxy = DBusProxy::new(connection).await?;
+    let busname = WellKnownName::try_from(format!("org.mpris.MediaPlayer2.{}", name))?;
+
+    // Asking for a name that can't be activated takes a while to fail, so check first
+    let activatable = dbus_proxy.list_activatable_names().await?;
+    if activatable
+        .iter()
+        .any(|name| matches!(name.inner(), BusName::WellKnown(name) if *name == busname))
+    {
+        dbus_proxy.start_service_by_name(busname, 0).await?;
+        return Ok(());
+    }
+
+    let exec = desktop_exec(name).ok_or_else(|| {
+        format!(
+            "{} can neither be activated over D-Bus nor be found as a desktop entry",
+            name
+        )
+    })?;
+
+    // The mediaplayer runs on its own, and keeps running after lizzy exits
+    Command::new("sh")
+        .arg("-c")
+        .arg(exec)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()?;
+
+    Ok(())
+}
@@ -0,0 +1,1308 @@
+//! The inner workings of lizzy, which follows mediaplayers over MPRIS for status bars such as Waybar
+//!
+//! Besides being the command line tool, lizzy can be embedded in bars and other applications written in
+//! Rust through [`updates`], which gives a stream of the media lizzy would display.
+
+use anyhow::{Context, Result};
+use control::Request;
+use matcher::Pattern;
+use media::{Media, Metadata};
+use options::Arguments;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, watch, Notify};
+use tokio::time::Interval;
+use tracing::{debug, error, info};
+use track::Track;
+use zbus::fdo::DBusProxy;
+use zbus::names::BusName;
+use zbus::names::OwnedBusName;
+use zbus::zvariant::Array;
+use zbus::zvariant::Dict;
+use zbus::zvariant::NoneValue;
+use zbus::zvariant::ObjectPath;
+use zbus::zvariant::OwnedValue;
+use zbus::zvariant::Value;
+use zbus::Connection;
+use zbus::Proxy;
+mod art;
+mod audio;
+mod autotoggle;
+mod cmus;
+mod commands;
+pub mod config;
+mod control;
+mod discord;
+mod docs;
+mod embed;
+mod events;
+mod fifo;
+mod file;
+mod follow;
+mod hash;
+mod history;
+mod hook;
+mod idle;
+mod launch;
+pub mod logging;
+mod logind;
+pub mod matcher;
+pub mod media;
+mod mpd;
+mod mqtt;
+mod notify;
+pub mod options;
+mod play;
+mod position;
+mod reload;
+mod scrobble;
+mod server;
+mod service;
+mod signals;
+mod streams;
+mod systemd;
+pub mod template;
+mod track;
+mod waybar;
+mod xdg;
+
+pub use embed::{updates, MediaStream, MediaUpdate};
+/// The error of anything that can go wrong, from the session bus to the mediaplayers
+pub type BoxedError = Box<dyn std::error::Error + Send + Sync>;
+
+/// State shared between the streams
+#[derive(Default)]
+struct State {
+    /// Unique bus names of the mediaplayers in order of activity when following, the most recent last
+    active_players: Mutex<Vec<String>>,
+    /// The track currently displayed, to tell when it changes
+    current_track: Mutex<Option<Track>>,
+    /// The id of the last desktop notification, so that it can be replaced
+    notification_id: AtomicU32,
+    /// The media currently displayed, to scroll it and to move its position on
+    displayed: Mutex<Option<Media>>,
+    /// How far the displayed text has scrolled
+    scroll_offset: AtomicUsize,
+    /// When the displayed media was paused, to hide it after a while
+    paused_since: Mutex<Option<Instant>>,
+    /// Unique bus name of the mediaplayer that was last displayed when listening to all of them
+    last_sender: Mutex<Option<String>>,
+    /// Unique bus names of the mediaplayers by their well-known names, until the owner changes
+    name_owners: Mutex<HashMap<String, String>>,
+    /// The pause that autotoggle made, so that it is only undone when lizzy was the one pausing
+    autopause: Mutex<Autopause>,
+    /// Bus name of the mediaplayer that was paused when the session locked, to resume it on unlock
+    lock_paused: Mutex<Option<String>>,
+    /// Bus name of the mediaplayer that was paused when the system suspended, to resume it on wake
+    suspend_paused: Mutex<Option<String>>,
+    /// Bus name of the mediaplayer that was paused when the microphone came into use, to resume it afterwards
+    microphone_paused: Mutex<Option<String>>,
+    /// The cookie of the idle inhibitor taken while playing, to release it again
+    idle_cookie: Mutex<Option<u32>>,
+    /// Where to pass the Rich Presence on to Discord, when publishing it
+    presence: Option<watch::Sender<Option<discord::Presence>>>,
+    /// Where to pass what is playing on to clients of the HTTP server and the event socket
+    now_playing: Option<watch::Sender<Option<String>>>,
+    /// Where the media goes instead of the output, when lizzy is embedded as a library
+    updates: Option<mpsc::Sender<MediaUpdate>>,
+    /// What was last written to the output file, so that it is only rewritten when it changes
+    written: Mutex<Option<String>>,
+    /// Where to pass what is playing on to the MQTT broker, when publishing it
+    mqtt: Option<watch::Sender<Option<String>>>,
+    /// How long the displayed track has been listened to, for submitting it to ListenBrainz
+    scrobbler: Mutex<scrobble::Scrobbler>,
+    /// How long the displayed track has been played, for recording it in the history
+    history: Mutex<history::History>,
+    /// Told whenever the config file has been read again, so that the output follows it
+    config_reloaded: Notify,
+    /// Where to pass what is playing on to be signalled to Waybar, when it changes
+    waybar: Option<watch::Sender<Option<String>>>,
+    /// The names of mediaplayers without the MPRIS prefix by their unique names, until a name changes owner
+    names: Mutex<HashMap<String, Vec<String>>>,
+    /// The identities of mediaplayers by their unique names, which are never reused while the session bus lasts
+    identities: Mutex<HashMap<String, String>>,
+    /// Where the mediaplayers are in their tracks, to work out their positions without asking
+    clocks: position::Clocks,
+    /// The album art of tracks, some of which is being downloaded
+    art: art::Art,
+    /// Where the output goes, along with what was last sent there
+    sink: media::Sink,
+}
+
+/// A mediaplayer that autotoggle paused, along with the mediaplayers that interrupted it
+#[derive(Default)]
+struct Autopause {
+    /// Bus name of the paused mediaplayer
+    mediaplayer: Option<String>,
+    /// Unique bus names of the mediaplayers still playing since they interrupted it
+    interrupters: Vec<String>,
+    /// When autotoggle last toggled each mediaplayer, to break loops of pausing and resuming
+    toggled: HashMap<String, Instant>,
+}
+
+impl State {
+    /// Forget what is known about the mediaplayers on a connection to the session bus that is gone
+    fn forget_bus(&self) {
+        self.active_players
+            .lock()
+            .expect("Active players lock poisoned")
+            .clear();
+        self.name_owners
+            .lock()
+            .expect("Name owners lock poisoned")
+            .clear();
+        // A new session bus hands out the same unique names again
+        self.identities
+            .lock()
+            .expect("Identities lock poisoned")
+            .clear();
+        self.names.lock().expect("Names lock poisoned").clear();
+        self.clocks.forget();
+        *self.last_sender.lock().expect("Last sender lock poisoned") = None;
+        *self.autopause.lock().expect("Autopause lock poisoned") = Autopause::default();
+        // The screensaver drops the inhibitors of a connection that is gone by itself
+        *self.idle_cookie.lock().expect("Idle cookie lock poisoned") = None;
+    }
+}
+
+/// Check if a mediaplayer name matches any of the mediaplayers the user wants to ignore
+fn is_ignored(options: &Arguments, name: &str) -> bool {
+    options.ignore().iter().any(|pattern| pattern.matches(name))
+}
+
+/// Names of mediaplayers that pass on other mediaplayers, such as those of a phone through KDE Connect or BlueZ
+const MIRROR_PREFIXES: [&str; 3] = ["kdeconnect", "bluez", "playerctld"];
+
+/// Check if a mediaplayer mirrors another one that is running, telling by its name and the track it has
+async fn is_mirror(
+    connection: &Connection,
+    state: &State,
+    proxy: &DBusProxy<'_>,
+    busname: &str,
+) -> bool {
+    let is_proxy = |name: &str| {
+        MIRROR_PREFIXES
+            .iter()
+            .any(|prefix| name.starts_with(prefix))
+    };
+
+    // Mirrors have tracks ids of their own, so the title and artist are what give them away
+    let track = |busname: String| async move {
+        let value = get_property(connection, &busname, "Metadata").await.ok()?;
+        let metadata = unpack_metadata(&value).ok()?;
+        Some((metadata.title?, metadata.artist))
+    };
+
+    let Some(name) = mediaplayer_name(connection, state, busname).await else {
+        return false;
+    };
+    if !is_proxy(&name) {
+        return false;
+    }
+    let Some(mirrored) = track(busname.to_string()).await else {
+        return false;
+    };
+
+    for other in list_mediaplayers(proxy).await.unwrap_or_default() {
+        if is_proxy(&other) {
+            continue;
+        }
+        if track(format!("org.mpris.MediaPlayer2.{}", other))
+            .await
+            .as_ref()
+            == Some(&mirrored)
+        {
+            return true;
+        }
+    }
+
+    false
+}
+
+/// Helper function to get the first string of a metadata array, such as the artists
+fn first_in_array(dict: &Dict, key: &str) -> Result<Option<String>, BoxedError> {
+    let array: Option<Array> = dict
+        .get(&key)
+        .with_context(|| format!("No key for {} found.", key))?;
+
+    if let Some(array) = array {
+        Ok(array.get(0).context("No entry found in array")?)
+    } else {
+        Ok(None)
+    }
+}
+
+/// Get every entry in an array of strings in the metadata, such as all artists of a track
+fn all_in_array(dict: &Dict, key: &str) -> Result<Vec<String>, BoxedError> {
+    let array: Option<Array> = dict
+        .get(&key)
+        .with_context(|| format!("No key for {} found.", key))?;
+
+    let Some(array) = array else {
+        return Ok(Vec::new());
+    };
+
+    (0..array.len())
+        .filter_map(|index| array.get::<String>(index).transpose())
+        .collect::<Result<_, _>>()
+        .map_err(Into::into)
+}
+
+/// Helper function to unpack the media metadata properties
+fn unpack_metadata(metadata: &Value<'_>) -> Result<Metadata, BoxedError> {
+    let dict: Dict = metadata
+        .downcast_ref()
+        .context("No dictionary of metadata found.")?;
+    let title: Option<String> = dict
+        .get(&"xesam:title")
+        .context("No key for xesam:title found.")?;
+    let album: Option<String> = dict
+        .get(&"xesam:album")
+        .context("No key for xesam:album found.")?;
+
+    // The length should be an i64 but some mediaplayers send an u64 instead
+    let length: Option<i64> = dict.get(&"mpris:length").ok().flatten().or_else(|| {
+        dict.get::<_, u64>(&"mpris:length")
+            .ok()
+            .flatten()
+            .map(|length| length as i64)
+    });
+
+    // The trackid should be an object path but some mediaplayers send a string instead
+    let trackid: Option<String> = dict
+        .get::<_, ObjectPath>(&"mpris:trackid")
+        .ok()
+        .flatten()
+        .map(|trackid| trackid.to_string())
+        .or_else(|| dict.get(&"mpris:trackid").ok().flatten());
+
+    // Get the first artist in the artist arrays
+    let artist = first_in_array(&dict, "xesam:artist")?;
+    let artists = all_in_array(&dict, "xesam:artist")?;
+    let albumartist = first_in_array(&dict, "xesam:albumArtist")?;
+
+    // A rating set by the user is preferred over one the mediaplayer came up with
+    let rating: Option<f64> = dict
+        .get(&"xesam:userRating")
+        .ok()
+        .flatten()
+        .or_else(|| dict.get(&"xesam:autoRating").ok().flatten());
+
+    // Web streams and videos tell where they come from, which is shown as the url or its domain
+    let url: Option<String> = dict.get(&"xesam:url").ok().flatten();
+
+    // Everything else is kept as it is, for the format to pick from
+    let raw: HashMap<String, OwnedValue> = dict.try_clone()?.try_into()?;
+
+    Ok(Metadata {
+        artist,
+        artists,
+        title,
+        album,
+        albumartist,
+        length,
+        trackid,
+        rating,
+        // Art is resolved only for the media that is output, since it may have to be downloaded
+        art: None,
+        url,
+        raw,
+    })
+}
+
+/// List the names of all running MPRIS mediaplayers, without the MPRIS prefix
+async fn list_mediaplayers(proxy: &DBusProxy<'_>) -> Result<Vec<String>, BoxedError> {
+    let all_names: Vec<OwnedBusName> = proxy.list_names().await?;
+
+    // Only the human readable names of MPRIS players are of interest
+    Ok(all_names
+        .iter()
+        .filter_map(|name| match name.inner() {
+            BusName::WellKnown(bus_name) => bus_name
+                .as_str()
+                .strip_prefix("org.mpris.MediaPlayer2.")
+                .map(String::from),
+            _ => None, // Skip non WellKnown variants
+        })
+        .collect())
+}
+
+/// Get the first name owner that matches the mediaplayer patterns, in order of priority
+async fn get_first_match(
+    proxy: &DBusProxy<'_>,
+    state: &State,
+    options: &Arguments,
+) -> Result<Option<BusName<'static>>, BoxedError> {
+    let mediaplayer_names: Vec<String> = list_mediaplayers(proxy)
+        .await?
+        .into_iter()
+        .filter(|name| !is_ignored(options, name))
+        .collect();
+
+    // The first pattern with a running mediaplayer wins
+    for pattern in &options.mediaplayers() {
+        let mut matching: Vec<&String> = Vec::new();
+        for name in &mediaplayer_names {
+            let busname = format!("org.mpris.MediaPlayer2.{}", name);
+            let patterns = std::slice::from_ref(pattern);
+            if matches_any(proxy.inner().connection(), state, patterns, name, &busname).await {
+                matching.push(name);
+            }
+        }
+
+        // Of several mediaplayers matching the same pattern the one playing is preferred, otherwise the first
+        let mut first_matching_name = matching.first().copied();
+        if matching.len() > 1 {
+            for name in &matching {
+                let busname = format!("org.mpris.MediaPlayer2.{}", name);
+                if let Ok(value) =
+                    get_property(proxy.inner().connection(), &busname, "PlaybackStatus").await
+                {
+                    if value
+                        .downcast_ref::<String>()
+                        .is_ok_and(|status| status == "Playing")
+                    {
+                        first_matching_name = Some(name);
+                        break;
+                    }
+                }
+            }
+        }
+
+        if let Some(name) = first_matching_name {
+            return Ok(Some(BusName::try_from(format!(
+                "org.mpris.MediaPlayer2.{}",
+                name
+            ))?));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Get the unique name of the owner of a bus name, only asking the bus if it isn't cached since the last change of owner
+async fn name_owner(connection: &Connection, state: &State, busname: &str) -> Option<String> {
+    // A unique name owns itself
+    if busname.starts_with(':') {
+        return Some(busname.to_string());
+    }
+
+    if let Some(owner) = state
+        .name_owners
+        .lock()
+        .expect("Name owners lock poisoned")
+        .get(busname)
+    {
+        return Some(owner.clone());
+    }
+
+    let owner = DBusProxy::new(connection)
+        .await
+        .ok()?
+        .get_name_owner(BusName::try_from(busname).ok()?)
+        .await
+        .ok()?
+        .to_string();
+
+    state
+        .name_owners
+        .lock()
+        .expect("Name owners lock poisoned")
+        .insert(busname.to_string(), owner.clone());
+
+    Some(owner)
+}
+
+/// Check if the sender of a message owns any mediaplayer name that matches the patterns
+async fn sender_matches(
+    proxy: &DBusProxy<'_>,
+    state: &State,
+    patterns: &[Pattern],
+    sender_busname: &str,
+) -> Result<bool, BoxedError> {
+    // No need to ask anything if there is nothing to match
+    if patterns.is_empty() {
+        return Ok(false);
+    }
+
+    // A well-known name is as good as the unique name that owns it
+    let connection = proxy.inner().connection();
+    let sender = match sender_busname.starts_with(':') {
+        true => sender_busname.to_string(),
+        false => match name_owner(connection, state, sender_busname).await {
+            Some(owner) => owner,
+            None => return Ok(false),
+        },
+    };
+
+    for name in mediaplayer_names(connection, state, &sender).await {
+        if matches_any(connection, state, patterns, &name, &sender).await {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
+/// Move the sender to the top of the active mediaplayers if it changed state, and tell if it is the one to display
+fn follow_sender(state: &State, sender_busname: &str, changed_state: bool) -> bool {
+    let mut active_players = state
+        .active_players
+        .lock()
+        .expect("Active players lock poisoned");
+
+    // The first mediaplayer we hear from is active until another one changes state
+    if changed_state || active_players.is_empty() {
+        active_players.retain(|busname| busname != sender_busname);
+        active_players.push(sender_busname.to_string());
+    }
+
+    active_players
+        .last()
+        .is_some_and(|busname| busname == sender_busname)
+}
+
+/// Remove a closed mediaplayer from the active mediaplayers, returning the next one to display if it was active
+fn unfollow(state: &State, busname: &str) -> Option<Option<String>> {
+    let mut active_players = state
+        .active_players
+        .lock()
+        .expect("Active players lock poisoned");
+
+    let was_active = active_players
+        .last()
+        .is_some_and(|active| active == busname);
+    active_players.retain(|active| active != busname);
+
+    was_active.then(|| active_players.last().cloned())
+}
+
+/// Get either metadata or playback status from the MPRIS properties
+async fn get_property(
+    connection: &Connection,
+    bus_name: &str,
+    property: &str,
+) -> Result<Value<'static>, BoxedError> {
+    // Create a proxy to help us get properties
+    let proxy = Proxy::new(
+        connection,
+        bus_name,
+        "/org/mpris/MediaPlayer2",
+        "org.mpris.MediaPlayer2.Player",
+    )
+    .await?;
+
+    Ok(proxy.get_property(property).await?)
+}
+
+/// Set a property of the MPRIS player interface, such as Shuffle
+async fn set_property(
+    connection: &Connection,
+    bus_name: &str,
+    property: &str,
+    value: Value<'_>,
+) -> Result<(), BoxedError> {
+    let proxy = Proxy::new(
+        connection,
+        bus_name,
+        "/org/mpris/MediaPlayer2",
+        "org.mpris.MediaPlayer2.Player",
+    )
+    .await?;
+
+    Ok(proxy.set_property(property, value).await?)
+}
+
+/// Get the names a unique name owns without the MPRIS prefix, looking up all of them only if it is not known yet
+async fn mediaplayer_names(connection: &Connection, state: &State, unique: &str) -> Vec<String> {
+    if let Some(names) = state.names.lock().expect("Names lock poisoned").get(unique) {
+        return names.clone();
+    }
+
+    let Ok(dbus_proxy) = DBusProxy::new(connection).await else {
+        return Vec::new();
+    };
+    let mut found: HashMap<String, Vec<String>> = HashMap::new();
+    for name in list_mediaplayers(&dbus_proxy).await.unwrap_or_default() {
+        // The mediaplayer may have closed since we listed it, which is fine
+        if let Some(owner) = name_owner(
+            connection,
+            state,
+            &format!("org.mpris.MediaPlayer2.{}", name),
+        )
+        .await
+        {
+            found.entry(owner).or_default().push(name);
+        }
+    }
+
+    // Everything listed is remembered, along with a sender that owns no name at all
+    let names = found.get(unique).cloned().unwrap_or_default();
+    let mut cached = state.names.lock().expect("Names lock poisoned");
+    cached.extend(found);
+    cached.entry(unique.to_string()).or_default();
+
+    names
+}
+
+/// Forget the names of the owners of a name that changed owner
+fn forget_names(state: &State, owners: &[Option<&str>]) {
+    let mut cached = state.names.lock().expect("Names lock poisoned");
+    for owner in owners.iter().flatten() {
+        cached.remove(*owner);
+    }
+}
+
+/// Get the name of a mediaplayer without the MPRIS prefix, looking it up if we only know its unique name
+async fn mediaplayer_name(connection: &Connection, state: &State, busname: &str) -> Option<String> {
+    if let Some(name) = busname.strip_prefix("org.mpris.MediaPlayer2.") {
+        return Some(name.to_string());
+    }
+
+    mediaplayer_names(connection, state, busname)
+        .await
+        .into_iter()
+        .next()
+}
+
+/// Get the identity of a mediaplayer, such as "VLC media player", asking it only once for each unique name
+async fn mediaplayer_identity(
+    connection: &Connection,
+    state: &State,
+    busname: &str,
+) -> Option<String> {
+    let owner = name_owner(connection, state, busname).await?;
+
+    if let Some(identity) = state
+        .identities
+        .lock()
+        .expect("Identities lock poisoned")
+        .get(&owner)
+    {
+        return Some(identity.clone());
+    }
+
+    let identity: String = get_root_property(connection, &owner, "Identity")
+        .await
+        .ok()?
+        .downcast()
+        .ok()?;
+    state
+        .identities
+        .lock()
+        .expect("Identities lock poisoned")
+        .insert(owner, identity.clone());
+
+    Some(identity)
+}
+
+/// Check if a mediaplayer matches any of the patterns, by its identity for those of --identity and otherwise by its name
+async fn matches_any(
+    connection: &Connection,
+    state: &State,
+    patterns: &[Pattern],
+    name: &str,
+    busname: &str,
+) -> bool {
+    for pattern in patterns {
+        let matched = match pattern.is_identity() {
+            true => mediaplayer_identity(connection, state, busname)
+                .await
+                .is_some_and(|identity| pattern.matches(&identity)),
+            false => pattern.matches(name),
+        };
+        if matched {
+            return true;
+        }
+    }
+    false
+}
+
+/// Get a property from the MPRIS root interface, such as the identity of the mediaplayer
+async fn get_root_property(
+    connection: &Connection,
+    bus_name: &str,
+    property: &str,
+) -> Result<Value<'static>, BoxedError> {
+    let proxy = Proxy::new(
+        connection,
+        bus_name,
+        "/org/mpris/MediaPlayer2",
+        "org.mpris.MediaPlayer2",
+    )
+    .await?;
+
+    Ok(proxy.get_property(property).await?)
+}
+
+/// Get all properties of the MPRIS player interface at once, which is one round trip instead of one for each
+async fn get_all_properties(
+    connection: &Connection,
+    bus_name: &str,
+) -> Result<HashMap<String, OwnedValue>, BoxedError> {
+    let reply = connection
+        .call_method(
+            Some(bus_name),
+            "/org/mpris/MediaPlayer2",
+            Some("org.freedesktop.DBus.Properties"),
+            "GetAll",
+            &("org.mpris.MediaPlayer2.Player",),
+        )
+        .await?;
+
+    Ok(reply.body().deserialize()?)
+}
+
+/// Parses arguments and unpacks metadata and playbackstatus as well as completes missing data
+async fn parse_msg_args(
+    connection: &Connection,
+    options: &Arguments,
+    state: &State,
+    changed_properties: &HashMap<&str, Value<'_>>,
+    mediaplayer_bus: &str,
+) -> Result<Media, BoxedError> {
+    // While we can receive metadata or playbackstatus, we never get them both.
+    // This is why we get all the properties at once to complete what is missing,
+    // where the changed ones are the more recent. This can fail which is fine.
+    let all_properties = get_all_properties(connection, mediaplayer_bus)
+        .await
+        .unwrap_or_default();
+    let property = |name: &str| {
+        changed_properties
+            .get(name)
+            .or_else(|| all_properties.get(name).map(|value| &**value))
+    };
+
+    // Handle metadata
+    let mut metadata = match property("Metadata") {
+        Some(metadata_value) => unpack_metadata(metadata_value)?,
+        None => Metadata::default(),
+    };
+
+    // Clean up the metadata as the user wants it before anything else sees it
+    metadata.clean(options);
+
+    // Art is only useful as a local file, so remote art is downloaded to the cache in the background
+    if options.art {
+        let art_url = metadata
+            .raw
+            .get("mpris:artUrl")
+            .and_then(|url| url.downcast_ref::<String>().ok());
+        metadata.art = match art_url {
+            Some(art_url) => state.art.resolve(&art_url),
+            // Without art of its own, the cover of the release can be looked up if the user wants that
+            None if options.art_lookup => metadata
+                .artist
+                .as_deref()
+                .zip(metadata.title.as_deref())
+                .and_then(|(artist, title)| {
+                    state.art.lookup(artist, metadata.album.as_deref(), title)
+                }),
+            None => None,
+        };
+    }
+
+    // Then the playbackstatus
+    let playbackstatus = match property("PlaybackStatus") {
+        Some(playbackstatus_value) => Some(playbackstatus_value.downcast_ref::<String>()?),
+        None => None,
+    };
+
+    let position = current_position(
+        connection,
+        state,
+        mediaplayer_bus,
+        playbackstatus.as_deref(),
+        property("Position").and_then(|position_value| position_value.downcast_ref::<i64>().ok()),
+        property("Rate").and_then(|rate_value| rate_value.downcast_ref::<f64>().ok()),
+    )
+    .await;
+
+    // Shuffle, loop status and volume are optional in MPRIS, so they are often missing
+    let shuffle =
+        property("Shuffle").and_then(|shuffle_value| shuffle_value.downcast_ref::<bool>().ok());
+    let loop_status = property("LoopStatus")
+        .and_then(|loop_status_value| loop_status_value.downcast_ref::<String>().ok());
+    let volume =
+        property("Volume").and_then(|volume_value| volume_value.downcast_ref::<f64>().ok());
+
+    // The capabilities a mediaplayer doesn't report are taken as missing
+    let capabilities = CAPABILITIES
+        .iter()
+        .filter(|(capability, _)| {
+            property(capability)
+                .and_then(|capable| capable.downcast_ref::<bool>().ok())
+                .unwrap_or(false)
+        })
+        .map(|(_, class)| *class)
+        .collect();
+
+    let player = mediaplayer_name(connection, state, mediaplayer_bus).await;
+
+    let mut media = Media::new(
+        metadata,
+        playbackstatus,
+        position,
+        shuffle,
+        loop_status,
+        volume,
+        player,
+    );
+    media.identity = mediaplayer_identity(connection, state, mediaplayer_bus).await;
+    media.capabilities = capabilities;
+
+    Ok(media)
+}
+
+/// Get the position of a mediaplayer, worked out from its clock when it has one,
+/// or else started from the position and rate it reported
+async fn current_position(
+    connection: &Connection,
+    state: &State,
+    mediaplayer_bus: &str,
+    playbackstatus: Option<&str>,
+    position: Option<i64>,
+    rate: Option<f64>,
+) -> Option<i64> {
+    let owner = name_owner(connection, state, mediaplayer_bus).await?;
+    if let Some(position) = state.clocks.now(&owner) {
+        return Some(position);
+    }
+
+    // Position is never part of the changed properties, so it comes from asking for all of them to start the clock
+    // Not all mediaplayers support this, which is fine
+    let position = position?;
+    state.clocks.start(
+        &owner,
+        position,
+        rate.unwrap_or(1.0),
+        playbackstatus == Some("Playing"),
+    );
+
+    Some(position)
+}
+
+/// Output the displayed media again at the position it has reached by now, if it is of the given mediaplayer
+async fn move_on(
+    connection: &Connection,
+    options: &Arguments,
+    state: &State,
+    busname: &str,
+) -> bool {
+    let Some(mut media) = state
+        .displayed
+        .lock()
+        .expect("Displayed lock poisoned")
+        .clone()
+    else {
+        return false;
+    };
+    let Some(player) = media.player.as_deref() else {
+        return false;
+    };
+
+    let owner = name_owner(connection, state, busname).await;
+    let displayed_owner = name_owner(
+        connection,
+        state,
+        &format!("org.mpris.MediaPlayer2.{}", player),
+    )
+    .await;
+    let Some(owner) = owner.filter(|owner| Some(owner) == displayed_owner.as_ref()) else {
+        return false;
+    };
+
+    // Only a mediaplayer with a clock has a position to move on
+    let Some(position) = state.clocks.now(&owner) else {
+        return false;
+    };
+    media.position = Some(position);
+    output(connection, options, state, Some(&media)).await;
+
+    true
+}
+
+/// Calls a method on the interface to play or pause what is currently playing
+async fn toggle_playback(
+    connection: &Connection,
+    bus_name: &str,
+    cmd: &str,
+) -> Result<(), BoxedError> {
+    // Create a proxy to help us get properties
+    let proxy = Proxy::new(
+        connection,
+        bus_name,
+        "/org/mpris/MediaPlayer2",
+        "org.mpris.MediaPlayer2.Player",
+    )
+    .await?;
+
+    // Mediaplayers tell what they are capable of, and calling anything else is pointless at best
+    let capability = match cmd {
+        "Play" => Some("CanPlay"),
+        "Pause" | "PlayPause" => Some("CanPause"),
+        "Next" => Some("CanGoNext"),
+        "Previous" => Some("CanGoPrevious"),
+        _ => None,
+    };
+    for capability in std::iter::once("CanControl").chain(capability) {
+        // A mediaplayer that doesn't report a capability is assumed to have it
+        if proxy
+            .get_property::<bool>(capability)
+            .await
+            .is_ok_and(|capable| !capable)
+        {
+            info!(
+                player = bus_name,
+                event = "skipped",
+                "Skipping {} on {}, which reports {} as false",
+                cmd,
+                bus_name,
+                capability
+            );
+            return Ok(());
+        }
+    }
+
+    Ok(proxy.call_noreply(cmd, &()).await?)
+}
+
+/// The capabilities of the MPRIS player interface, along with the CSS classes they are shown as, so that a bar can grey out what a mediaplayer can't do
+const CAPABILITIES: [(&str, &str); 5] = [
+    ("CanControl", "can-control"),
+    ("CanPlay", "can-play"),
+    ("CanPause", "can-pause"),
+    ("CanGoNext", "can-go-next"),
+    ("CanGoPrevious", "can-go-previous"),
+];
+
+/// Resolve the mediaplayer to use for one-shot actions, preferring one that is playing if none is defined
+async fn resolve_mediaplayer(
+    connection: &Connection,
+    options: &Arguments,
+    state: &State,
+) -> Result<Option<String>, BoxedError> {
+    let dbus_proxy = DBusProxy::new(connection).await?;
+
+    if !options.mediaplayers().is_empty() {
+        return Ok(get_first_match(&dbus_proxy, state, options)
+            .await?
+            .map(|busname| busname.to_string()));
+    }
+
+    let mut first_busname = None;
+    for name in list_mediaplayers(&dbus_proxy).await? {
+        if is_ignored(options, &name) {
+            continue;
+        }
+        let busname = format!("org.mpris.MediaPlayer2.{}", name);
+        let playbackstatus: Option<String> =
+            match get_property(connection, &busname, "PlaybackStatus").await {
+                Ok(value) => value.downcast().ok(),
+                Err(_) => None,
+            };
+
+        // Take the first one playing, otherwise the first one we found
+        if playbackstatus.as_deref() == Some("Playing") {
+            return Ok(Some(busname));
+        }
+        first_busname.get_or_insert(busname);
+    }
+
+    Ok(first_busname)
+}
+
+/// Output every running mediaplayer at once, in the order they are found on the bus
+async fn output_all(
+    connection: &Connection,
+    options: &Arguments,
+    state: &State,
+) -> Result<(), BoxedError> {
+    let dbus_proxy = DBusProxy::new(connection).await?;
+    let mut all_media = Vec::new();
+    let mediaplayers = options.mediaplayers();
+
+    for name in list_mediaplayers(&dbus_proxy).await? {
+        // Only the mediaplayers the user is after count, if there are any
+        if is_ignored(options, &name)
+            || !(mediaplayers.is_empty()
+                || matches_any(
+                    connection,
+                    state,
+                    &mediaplayers,
+                    &name,
+                    &format!("org.mpris.MediaPlayer2.{}", name),
+                )
+                .await)
+        {
+            continue;
+        }
+
+        // The mediaplayer may have closed since we listed it, which is fine
+        let busname = format!("org.mpris.MediaPlayer2.{}", name);
+        if let Ok(media) =
+            parse_msg_args(connection, options, state, &HashMap::new(), &busname).await
+        {
+            all_media.push(media);
+        }
+    }
+
+    state.sink.send_all(options, &all_media);
+    Ok(())
+}
+
+/// Print the current media of the mediaplayer once
+async fn print_once(connection: &Connection, options: &Arguments) -> Result<(), BoxedError> {
+    // Printing once needs nothing that streams keep track of, only a place for the caches
+    let state = State::default();
+
+    if options.all_players {
+        return output_all(connection, options, &state).await;
+    }
+
+    state.sink.header(options);
+
+    // Print an empty line if there is nothing to show, so that there is always some output
+    let sent = match resolve_mediaplayer(connection, options, &state).await? {
+        Some(busname) => parse_msg_args(connection, options, &state, &HashMap::new(), &busname)
+            .await?
+            .send(options, &state.sink, 0),
+        None => false,
+    };
+    if !sent {
+        state.sink.clear(options);
+    }
+
+    Ok(())
+}
+
+/// Handle a request from the control socket, acting on the mediaplayer currently displayed
+async fn handle_request(
+    connection: &Connection,
+    dbus_proxy: &DBusProxy<'_>,
+    options: &Arguments,
+    state: &State,
+    request: Request,
+    mediaplayer_busname: &mut String,
+    pinned_busname: &mut Option<String>,
+) -> Result<(), String> {
+    match request {
+        Request::Switch(name) => {
+            let names: Vec<String> = list_mediaplayers(dbus_proxy)
+                .await
+                .map_err(|err| err.to_string())?
+                .into_iter()
+                .filter(|name| !is_ignored(options, name))
+                .collect();
+
+            let name = match name {
+                Some(name) => names
+                    .into_iter()
+                    .find(|running| *running == name)
+                    .ok_or(format!("{} is not running", name))?,
+                None => {
+                    // Cycle to the mediaplayer after the one displayed, comparing unique names
+                    let current_owner = name_owner(connection, state, mediaplayer_busname).await;
+                    let mut current_index = None;
+                    for (index, name) in names.iter().enumerate() {
+                        let owner = name_owner(
+                            connection,
+                            state,
+                            &format!("org.mpris.MediaPlayer2.{}", name),
+                        )
+                        .await;
+                        if owner.is_some() && owner == current_owner {
+                            current_index = Some(index);
+                            break;
+                        }
+                    }
+                    let next_index = current_index.map_or(0, |index| index + 1);
+                    names
+                        .get(next_index % names.len().max(1))
+                        .cloned()
+                        .ok_or("No mediaplayer is running")?
+                }
+            };
+
+            let busname = format!("org.mpris.MediaPlayer2.{}", name);
+            busname.clone_into(mediaplayer_busname);
+            *pinned_busname = Some(busname);
+            refresh(connection, options, state, mediaplayer_busname).await
+        }
+        _ if mediaplayer_busname.is_empty() => Err(String::from("Nothing is displayed")),
+        Request::Refresh => {
+            // Whoever asks has likely lost the output, such as a bar that restarted, so it is sent even if it is the same
+            state.sink.forget_last_output();
+            refresh(connection, options, state, mediaplayer_busname).await
+        }
+        Request::Call(method) => toggle_playback(connection, mediaplayer_busname, method)
+            .await
+            .map_err(|err| err.to_string()),
+    }
+}
+
+/// Render the output of a mediaplayer again
+async fn refresh(
+    connection: &Connection,
+    options: &Arguments,
+    state: &State,
+    mediaplayer_busname: &str,
+) -> Result<(), String> {
+    let media = parse_msg_args(
+        connection,
+        options,
+        state,
+        &HashMap::new(),
+        mediaplayer_busname,
+    )
+    .await
+    .map_err(|err| err.to_string())?;
+    output(connection, options, state, Some(&media)).await;
+    Ok(())
+}
+
+/// Output what should be displayed again, looking for the mediaplayer first unless it is pinned or followed
+async fn output_current(
+    connection: &Connection,
+    options: &Arguments,
+    state: &State,
+    mediaplayer_busname: &mut String,
+    pinned_busname: &Option<String>,
+) -> Result<(), String> {
+    if options.all_players {
+        return output_all(connection, options, state)
+            .await
+            .map_err(|err| err.to_string());
+    }
+
+    if let Some(pinned) = pinned_busname {
+        pinned.clone_into(mediaplayer_busname);
+    } else if !options.follow {
+        *mediaplayer_busname = match resolve_mediaplayer(connection, options, state).await {
+            Ok(Some(busname)) => busname,
+            _ => BusName::null_value().to_owned(),
+        };
+    }
+
+    if mediaplayer_busname.is_empty() {
+        output(connection, options, state, None).await;
+        Ok(())
+    } else {
+        refresh(connection, options, state, mediaplayer_busname).await
+    }
+}
+
+/// Check if the media has been paused for long enough to be hidden, keeping track of when the pause started
+fn paused_too_long(options: &Arguments, state: &State, media: Option<&Media>) -> bool {
+    let Some(timeout) = options.hide_paused_after else {
+        return false;
+    };
+
+    let mut paused_since = state
+        .paused_since
+        .lock()
+        .expect("Paused since lock poisoned");
+    match media.and_then(|media| media.playbackstatus.as_deref()) {
+        Some("Paused") => {
+            paused_since.get_or_insert_with(Instant::now).elapsed() >= Duration::from_secs(timeout)
+        }
+        _ => {
+            *paused_since = None;
+            false
+        }
+    }
+}
+
+/// Wait until the paused media should be hidden, or forever if it is not paused
+async fn hide_paused(options: &Arguments, state: &State) {
+    let deadline = options.hide_paused_after.and_then(|timeout| {
+        state
+            .paused_since
+            .lock()
+            .expect("Paused since lock poisoned")
+            .map(|paused_since| paused_since + Duration::from_secs(timeout))
+    });
+
+    match deadline {
+        Some(deadline) if deadline > Instant::now() => {
+            tokio::time::sleep_until(deadline.into()).await;
+        }
+        _ => std::future::pending().await,
+    }
+}
+
+/// Send the media output, or clear it with an empty line, and pass it on to anything else that is enabled
+async fn output(
+    connection: &Connection,
+    options: &Arguments,
+    state: &State,
+    media: Option<&Media>,
+) {
+    // Keep track of the displayed track to know when it changes
+    let track = media.map(Track::from_media);
+    let new_track = match (
+        &track,
+        state
+            .current_track
+            .lock()
+            .expect("Current track lock poisoned")
+            .as_ref(),
+    ) {
+        (Some(track), Some(previous)) => track.is_new(previous),
+        (Some(_), None) => true,
+        (None, _) => false,
+    };
+
+    // A new track starts scrolling from the beginning
+    if new_track {
+        state.scroll_offset.store(0, Ordering::Relaxed);
+    }
+
+    // Output that has been paused for long enough is hidden everywhere until playback resumes, while the track is still kept track of
+    let hidden = paused_too_long(options, state, media);
+    let shown = media.filter(|_| !hidden);
+
+    let sent = match (shown, &state.updates) {
+        // Embedding applications get the media itself rather than any output
+        (shown, Some(updates)) => {
+            let update = match shown {
+                Some(media) => MediaUpdate::Changed(Box::new(media.clone())),
+                None => MediaUpdate::Cleared,
+            };
+            updates.send(update).await.is_ok()
+        }
+        (Some(media), None) => media.send(
+            options,
+            &state.sink,
+            state.scroll_offset.load(Ordering::Relaxed),
+        ),
+        (None, None) => {
+            state.sink.clear(options);
+            true
+        }
+    };
+
+    if let Some(path) = &options.output_file {
+        file::write(path, options, &state.written, shown).await;
+    }
+
+    if sent {
+        let player = media
+            .and_then(|media| media.player.as_deref())
+            .unwrap_or("nothing");
+        debug!(
+            player,
+            event = "output",
+            "Updated the output with {}",
+            player
+        );
+
+        *state
+            .current_track
+            .lock()
+            .expect("Current track lock poisoned") = track;
+
+        // Remember what is displayed so that it can be scrolled and its position moved on
+        *state.displayed.lock().expect("Displayed lock poisoned") = media.cloned();
+
+        if let (true, Some(media)) = (new_track, media) {
+            if let Some(command) = &options.on_change {
+                hook::spawn(command, media);
+            }
+
+            if options.notify {
+                if let Err(err) =
+                    notify::send(connection, options, &state.notification_id, media).await
+                {
+                    error!(event = "notify", "Failed to send notification: {}", err);
+                }
+            }
+        }
+    }
+
+    if sent && options.dbus_service {
+        if let Err(err) = service::publish(connection, shown).await {
+            error!("Failed to publish state: {}", err);
+        }
+    }
+
+    if let Some(presence) = &state.presence {
+        discord::update(presence, shown);
+    }
+
+    if let Some(now_playing) = &state.now_playing {
+        media::share(now_playing, options, shown);
+    }
+
+    if let Some(mqtt) = &state.mqtt {
+        media::share(mqtt, options, shown);
+    }
+
+    if let Some(waybar) = &state.waybar {
+        media::share(waybar, options, shown);
+    }
+
+    if let Some(listenbrainz) = &options.config().listenbrainz {
+        scrobble::update(&state.scrobbler, listenbrainz, media);
+    }
+
+    if options.history {
+        history::update(&state.history, media);
+    }
+
+    if options.inhibit_idle {
+        let playing = media.is_some_and(|media| media.playbackstatus.as_deref() == Some("Playing"));
+        if let Err(err) = idle::inhibit(connection, &state.idle_cookie, playing).await {
+            error!(event = "idle", "Failed to inhibit idle: {}", err);
+        }
+    }
+}
+
+/// Wait for the next tick of the ticker, or forever if there is no ticker
+async fn tick(ticker: &mut Option<Interval>) {
+    match ticker {
+        Some(ticker) => {
+            ticker.tick().await;
+        }
+        None => std::future::pending().await,
+    }
+}
+
+/// The error for running out of file descriptors, as numbered on Linux
+const EMFILE: i32 = 24;
+
+/// How long to wait before accepting clients again when out of file descriptors
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Log a client that could not be accepted, waiting a moment when out of file descriptors so that some are closed first
+async fn accept_failed(socket: &str, err: std::io::Error) {
+    error!("Failed to accept a client of the {}: {}", socket, err);
+    if err.raw_os_error() == Some(EMFILE) {
+        tokio::time::sleep(ACCEPT_BACKOFF).await;
+    }
+}
+
+/// Run lizzy with the options of the command line, until it is killed
+pub async fn run(options: Arguments) -> Result<(), BoxedError> {
+    // Completions and the man page are only printed, so they work without a session bus
+    if let Some(docs) = options.command.as_ref().and_then(commands::Command::docs) {
+        print!("{}", docs);
+        return Ok(());
+    }
+
+    // Connect to the session bus
+    let connection = Connection::session().await?;
+
+    // Commands do their thing once, so there is no need for any streams
+    if let Some(command) = &options.command {
+        return command.run(&connection, &options).await;
+    }
+
+    if options.once {
+        return print_once(&connection, &options).await;
+    }
+
+    follow::follow(connection, Arc::new(options), None).await
+}
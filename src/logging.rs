@@ -0,0 +1,40 @@
+use crate::options::Arguments;
+use crate::BoxedError;
+use std::fs::OpenOptions;
+use std::io::IsTerminal;
+use std::sync::Mutex;
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::prelude::*;
+use tracing_subscriber::{fmt, Layer};
+
+/// Set up logging to stderr, or to a file if the user wants to, and optionally to the journal as well
+pub fn init(options: &Arguments) -> Result<(), BoxedError> {
+    let layer = fmt::layer().with_target(false);
+
+    let fmt_layer = match &options.log_file {
+        Some(path) => {
+            let file = OpenOptions::new().create(true).append(true).open(path)?;
+            layer.with_ansi(false).with_writer(Mutex::new(file)).boxed()
+        }
+        // Stdout is for the output, so logs go to stderr, in color only if someone is watching
+        None => layer
+            .with_ansi(std::io::stderr().is_terminal())
+            .with_writer(std::io::stderr)
+            .boxed(),
+    };
+
+    // The journal keeps the level as priority and the fields of each event, such as the player
+    let journald_layer = if options.log_journald {
+        Some(tracing_journald::layer()?.with_syslog_identifier(String::from("lizzy")))
+    } else {
+        None
+    };
+
+    tracing_subscriber::registry()
+        .with(LevelFilter::from_level(options.log_level))
+        .with(fmt_layer)
+        .with(journald_layer)
+        .init();
+
+    Ok(())
+}
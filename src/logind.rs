@@ -0,0 +1,149 @@
+use crate::options::Arguments;
+use crate::BoxedError;
+use tokio::sync::mpsc;
+use tracing::warn;
+use zbus::export::futures_util::stream::StreamExt;
+use zbus::proxy::SignalStream;
+use zbus::zvariant::{OwnedFd, OwnedObjectPath};
+use zbus::{Connection, Message, Proxy};
+
+const DESTINATION: &str = "org.freedesktop.login1";
+
+/// Changes of the session and the system that lizzy reacts to
+pub enum Event {
+    Lock,
+    Unlock,
+    // The system waits with suspending until the inhibitor lock is dropped, so drop it once playback is paused
+    Suspend(Option<OwnedFd>),
+    Wake,
+}
+
+/// The path of the session lizzy runs in, or the graphical session of the user when run as a service
+async fn session_path(
+    connection: &Connection,
+    manager: &Proxy<'_>,
+) -> Result<OwnedObjectPath, BoxedError> {
+    if let Ok(path) = manager.call("GetSession", &("auto",)).await {
+        return Ok(path);
+    }
+
+    // Services of the user manager are not part of any session, so take the one the user is displayed in
+    let user = Proxy::new(
+        connection,
+        DESTINATION,
+        "/org/freedesktop/login1/user/self",
+        "org.freedesktop.login1.User",
+    )
+    .await?;
+    let (id, path): (String, OwnedObjectPath) = user.get_property("Display").await?;
+
+    if id.is_empty() {
+        return Err("No session found to watch for locking".into());
+    }
+
+    Ok(path)
+}
+
+/// Ask logind to hold off suspending until lizzy is done with pausing playback
+async fn inhibit(manager: &Proxy<'_>) -> Option<OwnedFd> {
+    match manager
+        .call(
+            "Inhibit",
+            &(
+                "sleep",
+                "lizzy",
+                "Pausing the mediaplayer before suspending",
+                "delay",
+            ),
+        )
+        .await
+    {
+        Ok(fd) => Some(fd),
+        Err(err) => {
+            // Without the lock the system may suspend before the mediaplayer is paused, which is unfortunate but fine
+            warn!("Failed to delay suspending: {}", err);
+            None
+        }
+    }
+}
+
+/// Wait for the next signal on a stream, or forever if it is not listened to
+async fn next_signal(stream: &mut Option<SignalStream<'_>>) -> Option<Message> {
+    match stream {
+        Some(stream) => stream.next().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Listen on the system bus for the session locking and the system suspending, and pass it on to the property stream
+pub async fn watch(options: &Arguments, sender: mpsc::Sender<Event>) -> Result<(), BoxedError> {
+    let connection = Connection::system().await?;
+    let manager = Proxy::new(
+        &connection,
+        DESTINATION,
+        "/org/freedesktop/login1",
+        "org.freedesktop.login1.Manager",
+    )
+    .await?;
+
+    let (mut locks, mut unlocks) = if options.pause_on_lock {
+        let session = Proxy::new(
+            &connection,
+            DESTINATION,
+            session_path(&connection, &manager).await?,
+            "org.freedesktop.login1.Session",
+        )
+        .await?;
+        (
+            Some(session.receive_signal("Lock").await?),
+            Some(session.receive_signal("Unlock").await?),
+        )
+    } else {
+        (None, None)
+    };
+
+    let (mut sleeps, mut inhibitor) = if options.pause_on_suspend {
+        (
+            Some(manager.receive_signal("PrepareForSleep").await?),
+            inhibit(&manager).await,
+        )
+    } else {
+        (None, None)
+    };
+
+    loop {
+        let event = tokio::select! {
+            msg = next_signal(&mut locks) => msg.map(|_| Event::Lock),
+            msg = next_signal(&mut unlocks) => msg.map(|_| Event::Unlock),
+            msg = next_signal(&mut sleeps) => match msg {
+                // True means the system is about to suspend, and false that it woke up again
+                Some(msg) => match msg.body().deserialize::<bool>()? {
+                    true => Some(Event::Suspend(inhibitor.take())),
+                    false => {
+                        // The lock is gone after suspending, so take another one for the next time
+                        inhibitor = inhibit(&manager).await;
+                        Some(Event::Wake)
+                    }
+                },
+                None => None,
+            },
+        };
+
+        let Some(event) = event else {
+            return Err("Lost the system bus".into());
+        };
+
+        // The property stream is gone once lizzy exits, and then there is nothing left to do
+        if sender.send(event).await.is_err() {
+            return Ok(());
+        }
+    }
+}
+
+/// Wait for the next event of the session, or forever if the session is not watched
+pub async fn next_event(receiver: &mut Option<mpsc::Receiver<Event>>) -> Option<Event> {
+    match receiver {
+        Some(receiver) => receiver.recv().await,
+        None => std::future::pending().await,
+    }
+}
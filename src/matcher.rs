@@ -0,0 +1,244 @@
+use regex::{Regex, RegexBuilder};
+use std::borrow::Cow;
+
+/// A pattern to match mediaplayer names against
+#[derive(Clone)]
+pub struct Pattern {
+    kind: PatternKind,
+    case_insensitive: bool,
+    identity: bool,
+}
+
+#[derive(Clone)]
+enum PatternKind {
+    Exact(String),
+    Glob(Vec<char>),
+    Regex(Regex),
+}
+
+impl Pattern {
+    /// Construct a pattern, where a `re:` prefix makes it a regular expression
+    pub fn new(pattern: &str, case_insensitive: bool) -> Result<Self, regex::Error> {
+        let kind = if let Some(expression) = pattern.strip_prefix("re:") {
+            PatternKind::Regex(
+                RegexBuilder::new(expression)
+                    .case_insensitive(case_insensitive)
+                    .build()?,
+            )
+        } else {
+            // Compare everything in lowercase if casing should not matter
+            let pattern = if case_insensitive {
+                pattern.to_lowercase()
+            } else {
+                pattern.to_string()
+            };
+
+            // Check if the pattern contains any glob pattern characters
+            if pattern.contains(['*', '?', '[']) {
+                PatternKind::Glob(pattern.chars().collect())
+            } else {
+                PatternKind::Exact(pattern)
+            }
+        };
+
+        Ok(Pattern {
+            kind,
+            case_insensitive,
+            identity: false,
+        })
+    }
+
+    /// Match the identity of mediaplayers instead of their name, such as "Mozilla Firefox" rather than firefox.instance_1_23
+    pub fn for_identity(self) -> Self {
+        Pattern {
+            identity: true,
+            ..self
+        }
+    }
+
+    /// Check if the pattern is to be matched against the identity of mediaplayers
+    pub fn is_identity(&self) -> bool {
+        self.identity
+    }
+
+    /// The name the pattern matches, if it is a plain name rather than a glob or a regular expression
+    pub fn exact_name(&self) -> Option<&str> {
+        match &self.kind {
+            PatternKind::Exact(name) if !self.identity => Some(name),
+            _ => None,
+        }
+    }
+
+    /// Check if a mediaplayer name matches the pattern
+    pub fn matches(&self, name: &str) -> bool {
+        let name = if self.case_insensitive {
+            Cow::Owned(name.to_lowercase())
+        } else {
+            Cow::Borrowed(name)
+        };
+
+        match &self.kind {
+            // Mediaplayers that run several times add an instance to their name, such as firefox.instance_1_123,
+            // so a plain name matches all of its instances
+            PatternKind::Exact(pattern) => {
+                *pattern == name
+                    || (!self.identity
+                        && name
+                            .strip_prefix(pattern.as_str())
+                            .is_some_and(|instance| instance.starts_with(".instance")))
+            }
+            PatternKind::Glob(pattern) => {
+                let name: Vec<char> = name.chars().collect();
+                matches_glob_pattern(pattern, &name)
+            }
+            PatternKind::Regex(regex) => regex.is_match(&name),
+        }
+    }
+}
+
+/// Glob pattern match supporting `*`, `?` and character classes such as `[a-z]` or `[!0-9]`
+fn matches_glob_pattern(pattern: &[char], name: &[char]) -> bool {
+    let (mut p, mut n) = (0, 0);
+
+    // The position of the last star in the pattern and where in the name it started matching
+    let mut star: Option<(usize, usize)> = None;
+
+    while n < name.len() {
+        let next = match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, n));
+                p += 1;
+                continue;
+            }
+            Some('?') => Some(p + 1),
+            Some('[') => match match_class(pattern, p, name[n]) {
+                Some((true, end)) => Some(end),
+                Some((false, _)) => None,
+                // An unclosed bracket is just a bracket
+                None => (name[n] == '[').then_some(p + 1),
+            },
+            Some(c) => (*c == name[n]).then_some(p + 1),
+            None => None,
+        };
+
+        match (next, star) {
+            (Some(next), _) => {
+                p = next;
+                n += 1;
+            }
+            // On a mismatch we let the last star swallow one more character and try again
+            (None, Some((star_p, star_n))) => {
+                star = Some((star_p, star_n + 1));
+                p = star_p + 1;
+                n = star_n + 1;
+            }
+            (None, None) => return false,
+        }
+    }
+
+    // Whatever is left of the pattern can only be stars
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// Match a character against the class starting at `start`, returning the result and the position after the class
+fn match_class(pattern: &[char], start: usize, c: char) -> Option<(bool, usize)> {
+    let mut i = start + 1;
+
+    let negated = matches!(pattern.get(i), Some('!' | '^'));
+    if negated {
+        i += 1;
+    }
+
+    let mut matched = false;
+    let mut first = true;
+
+    loop {
+        match pattern.get(i)? {
+            // A closing bracket first in the class is taken literally
+            ']' if !first => return Some((matched != negated, i + 1)),
+            low => {
+                // Check for a range such as a-z
+                if pattern.get(i + 1) == Some(&'-') && pattern.get(i + 2).is_some_and(|c| *c != ']')
+                {
+                    let high = pattern[i + 2];
+                    matched |= (*low..=high).contains(&c);
+                    i += 3;
+                } else {
+                    matched |= *low == c;
+                    i += 1;
+                }
+            }
+        }
+        first = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(pattern: &str, name: &str) -> bool {
+        Pattern::new(pattern, false)
+            .expect("invalid pattern")
+            .matches(name)
+    }
+
+    #[test]
+    fn matches_any_single_character() {
+        assert!(matches("spot?fy", "spotify"));
+        assert!(!matches("spot?fy", "spotfy"));
+        assert!(!matches("spot?fy", "spotiffy"));
+    }
+
+    #[test]
+    fn matches_character_classes() {
+        assert!(matches("mpv[0-9]", "mpv2"));
+        assert!(!matches("mpv[0-9]", "mpvx"));
+        assert!(matches("mpv[!0-9]", "mpvx"));
+        assert!(!matches("mpv[!0-9]", "mpv2"));
+        assert!(matches("mpv[^0-9]", "mpvx"));
+        assert!(matches("[]]", "]"));
+        // An unclosed bracket is just a bracket
+        assert!(matches("mpv[", "mpv["));
+        assert!(!matches("mpv[", "mpv"));
+    }
+
+    #[test]
+    fn matches_several_stars() {
+        assert!(matches("*fire*fox*", "firefox.instance_1_123"));
+        assert!(matches("a*b*c", "axxbyyc"));
+        assert!(matches("a*b*c", "abc"));
+        assert!(!matches("a*b*c", "axxcyyb"));
+        assert!(matches("**", ""));
+    }
+
+    #[test]
+    fn matches_all_instances_of_a_plain_name() {
+        assert!(matches("firefox", "firefox"));
+        assert!(matches("firefox", "firefox.instance_1_123"));
+        assert!(!matches("firefox", "firefoxy"));
+        assert!(!matches("firefox", "firefox2.instance_1_123"));
+        assert!(!Pattern::new("Firefox", false)
+            .expect("invalid pattern")
+            .for_identity()
+            .matches("Firefox.instance_1_123"));
+    }
+
+    #[test]
+    fn ignores_casing_if_asked() {
+        let pattern = Pattern::new("Spot*", true).expect("invalid pattern");
+        assert!(pattern.matches("spotify"));
+        assert!(pattern.matches("SPOTIFY"));
+        assert!(!matches("Spot*", "spotify"));
+    }
+
+    #[test]
+    fn matches_regular_expressions() {
+        assert!(matches(
+            r"re:^chromium\.instance\d+$",
+            "chromium.instance42"
+        ));
+        assert!(!matches(r"re:^chromium\.instance\d+$", "chromium.instance"));
+        assert!(Pattern::new("re:(", false).is_err());
+    }
+}
@@ -1,46 +1,775 @@
-use serde_json::json;
-pub struct Media {
+use crate::config::{Field, Rule, TITLE_CLEANER};
+use crate::options::{Arguments, Escape, OutputMode, RatingStyle};
+use crate::template::Template;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use tokio::process::Command;
+use tokio::sync::watch;
+use tracing::error;
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+use zbus::zvariant::{self, OwnedValue};
+
+/// The metadata properties we care about from the MPRIS metadata
+#[derive(Clone, Default)]
+pub struct Metadata {
     pub artist: Option<String>,
+    pub artists: Vec<String>,
     pub title: Option<String>,
+    pub album: Option<String>,
+    pub albumartist: Option<String>,
+    pub length: Option<i64>,
+    pub trackid: Option<String>,
+    pub rating: Option<f64>,
+    pub art: Option<String>,
+    pub url: Option<String>,
+    /// All of the metadata as the mediaplayer sent it, for {{meta:KEY}}
+    pub raw: HashMap<String, OwnedValue>,
+}
+
+impl Metadata {
+    /// Apply the replace rules of the config file, in the order they are written
+    pub fn rewrite(&mut self, rules: &[Rule]) {
+        for rule in rules {
+            let value = match rule.field {
+                Field::Artist => &mut self.artist,
+                Field::Title => &mut self.title,
+                Field::Album => &mut self.album,
+            };
+            if let Some(value) = value {
+                *value = rule.apply(value);
+            }
+
+            // Every artist is cleaned up the same way as the first one
+            if rule.field == Field::Artist {
+                for artist in &mut self.artists {
+                    *artist = rule.apply(artist);
+                }
+            }
+        }
+    }
+
+    /// Every key of the metadata with its value on a line of its own, to see what a mediaplayer sends
+    pub fn dump(&self, separator: &str) -> String {
+        let mut keys: Vec<&String> = self.raw.keys().collect();
+        keys.sort();
+
+        keys.into_iter()
+            .map(|key| {
+                let value = &self.raw[key];
+                let text = metadata_text(value, separator).unwrap_or_else(|| value.to_string());
+                format!("{}: {}", key, text)
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Clean up the metadata as the user wants it, before anything else sees it
+    pub fn clean(&mut self, options: &Arguments) {
+        if options.clean_titles {
+            self.rewrite(&TITLE_CLEANER);
+        }
+        self.rewrite(&options.config().replace);
+    }
+}
+
+/// Format a time in microseconds as m:ss, or h:mm:ss if it is long enough
+fn format_time(microseconds: i64) -> String {
+    let seconds = microseconds.max(0) / 1_000_000;
+    let (hours, minutes, seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+
+    if hours > 0 {
+        format!("{}:{:02}:{:02}", hours, minutes, seconds)
+    } else {
+        format!("{}:{:02}", minutes, seconds)
+    }
+}
+
+/// The width of {{progress}} when none is given
+const PROGRESS_WIDTH: usize = 10;
+
+/// Show how far the track has played as a bar of a number of columns, such as ▰▰▰▱▱▱▱▱▱▱
+fn progress_bar(position: i64, length: i64, width: usize) -> Option<String> {
+    if length <= 0 {
+        return None;
+    }
+    let filled = (position.clamp(0, length) as f64 / length as f64 * width as f64).round() as usize;
+
+    Some(format!(
+        "{}{}",
+        "▰".repeat(filled),
+        "▱".repeat(width - filled)
+    ))
+}
+
+/// Show a rating between 0 and 1 as a number of stars out of five
+fn format_rating(rating: f64, style: RatingStyle) -> String {
+    let stars = (rating.clamp(0.0, 1.0) * 5.0).round() as usize;
+
+    match style {
+        RatingStyle::Stars => format!("{}{}", "★".repeat(stars), "☆".repeat(5 - stars)),
+        RatingStyle::Number => stars.to_string(),
+    }
+}
+
+/// Show a value of the metadata as text, with the items of a list joined like the artists
+fn metadata_text(value: &zvariant::Value<'_>, separator: &str) -> Option<String> {
+    let text = match value {
+        zvariant::Value::Str(text) => text.to_string(),
+        zvariant::Value::ObjectPath(path) => path.to_string(),
+        zvariant::Value::Bool(value) => value.to_string(),
+        zvariant::Value::U8(value) => value.to_string(),
+        zvariant::Value::I16(value) => value.to_string(),
+        zvariant::Value::U16(value) => value.to_string(),
+        zvariant::Value::I32(value) => value.to_string(),
+        zvariant::Value::U32(value) => value.to_string(),
+        zvariant::Value::I64(value) => value.to_string(),
+        zvariant::Value::U64(value) => value.to_string(),
+        zvariant::Value::F64(value) => value.to_string(),
+        zvariant::Value::Value(value) => metadata_text(value, separator)?,
+        zvariant::Value::Array(array) => array
+            .iter()
+            .filter_map(|value| metadata_text(value, separator))
+            .collect::<Vec<_>>()
+            .join(separator),
+        _ => return None,
+    };
+
+    Some(text).filter(|text| !text.is_empty())
+}
+
+/// The host of a url without the www, such as youtube.com for https://www.youtube.com/watch?v=...
+fn domain(url: &str) -> Option<String> {
+    let (_, rest) = url.split_once("://")?;
+    let authority = rest.split(['/', '?', '#']).next()?;
+    let host = authority.rsplit('@').next()?;
+    let host = host.split(':').next()?;
+    let host = host.strip_prefix("www.").unwrap_or(host);
+
+    // Local files have no host at all
+    Some(host.to_lowercase()).filter(|host| !host.is_empty())
+}
+
+/// Turn a mediaplayer name into something usable as a CSS class, such as firefox for firefox.instance_1_23
+fn css_class(player: &str) -> String {
+    player
+        .split('.')
+        .next()
+        .unwrap_or_default()
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c.to_ascii_lowercase()
+            } else {
+                '-'
+            }
+        })
+        .collect()
+}
+
+/// The most columns to put in a tmux status line, which has to share a terminal with everything else
+const TMUX_LENGTH: usize = 40;
+
+/// Shorten a text to a number of display columns, marking that it was cut with an ellipsis
+pub fn truncate(text: String, length: usize, ellipsis: &str) -> String {
+    if text.width() <= length {
+        return text;
+    }
+
+    // Wide characters such as CJK take two columns, so count columns rather than characters
+    let length = length.saturating_sub(ellipsis.width());
+    let mut width = 0;
+    let mut truncated: String = text
+        .chars()
+        .take_while(|c| {
+            width += c.width().unwrap_or(0);
+            width <= length
+        })
+        .collect();
+    truncated.push_str(ellipsis);
+    truncated
+}
+
+/// Escape text for what shows it, such as Waybar with pango markup where & would otherwise break the text
+fn escape(text: String, mode: Escape) -> String {
+    match mode {
+        Escape::None => text,
+        // Only the ampersand, as lizzy used to do, for bars that show everything else as it is
+        Escape::Ampersand => text.replace('&', "&amp;"),
+        Escape::Pango => text
+            .replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;")
+            .replace('\'', "&#39;")
+            .replace('"', "&quot;"),
+        // The text of a JSON string, without the quotes around it
+        Escape::Json => {
+            let json = serde_json::Value::from(text).to_string();
+            json[1..json.len() - 1].to_string()
+        }
+    }
+}
+
+/// Show a window of text that has scrolled a number of characters, wrapping around to the beginning
+fn scroll(text: &str, width: usize, offset: usize) -> String {
+    if text.width() <= width {
+        return text.to_string();
+    }
+
+    // Some space between the end and the beginning makes it clear where the text starts over
+    let chars: Vec<char> = text.chars().chain("   ".chars()).collect();
+    let mut columns = 0;
+
+    chars
+        .iter()
+        .cycle()
+        .skip(offset % chars.len())
+        .take_while(|c| {
+            columns += c.width().unwrap_or(0);
+            columns <= width
+        })
+        .collect()
+}
+
+#[derive(Clone)]
+pub struct Media {
+    pub metadata: Metadata,
     pub playbackstatus: Option<String>,
+    pub position: Option<i64>,
+    pub shuffle: Option<bool>,
+    pub loop_status: Option<String>,
+    pub volume: Option<f64>,
+    pub player: Option<String>,
+    pub identity: Option<String>,
+    pub capabilities: Vec<&'static str>,
 }
 
 impl Media {
     /// Construct a new instance of media output
     pub fn new(
-        artist: Option<String>,
-        title: Option<String>,
+        metadata: Metadata,
         playbackstatus: Option<String>,
+        position: Option<i64>,
+        shuffle: Option<bool>,
+        loop_status: Option<String>,
+        volume: Option<f64>,
+        player: Option<String>,
     ) -> Self {
         Media {
-            artist,
-            title,
+            metadata,
             playbackstatus,
+            position,
+            shuffle,
+            loop_status,
+            volume,
+            player,
+            identity: None,
+            capabilities: Vec::new(),
+        }
+    }
+
+    /// The playback progress in percent, if both position and length are known
+    fn percentage(&self) -> Option<i64> {
+        match (self.position, self.metadata.length) {
+            // Worked out wider than needed, since a bogus length could overflow
+            (Some(position), Some(length)) if length > 0 => {
+                Some((i128::from(position.clamp(0, length)) * 100 / i128::from(length)) as i64)
+            }
+            _ => None,
         }
     }
 
-    /// Send the media output to Waybar
-    pub fn send(&self, output_format: &str) {
-        // All fields must be some
-        if let Self {
-            artist: Some(artist),
-            title: Some(title),
+    /// Whether the percentage moves on by itself, which it does while playing a track of known length
+    pub fn progressing(&self) -> bool {
+        self.playbackstatus.as_deref() == Some("Playing") && self.percentage().is_some()
+    }
+
+    /// The user defined format for the playbackstatus, falling back to the main format
+    pub fn format(&self, options: &Arguments) -> Template {
+        match self.playbackstatus.as_deref() {
+            Some("Paused") => options.format_paused.clone(),
+            Some("Stopped") => options.format_stopped.clone(),
+            _ => None,
+        }
+        .unwrap_or_else(|| options.format())
+    }
+
+    /// Render a user defined template, as long as title and playbackstatus are known
+    pub fn render(&self, template: &Template, options: &Arguments) -> Option<String> {
+        let playbackstatus = self.playbackstatus.as_deref()?;
+
+        // Map the playbackstatus to the user defined icons, if any
+        let status = match playbackstatus {
+            "Playing" => options.playing_icon.as_deref(),
+            "Paused" => options.paused_icon.as_deref(),
+            _ => None,
+        }
+        .unwrap_or(playbackstatus);
+
+        self.fill(template, options, status)
+    }
+
+    /// Fill in the tags of a template, using the given text for the status
+    fn fill(&self, template: &Template, options: &Arguments, status: &str) -> Option<String> {
+        // Title and playbackstatus must be some, the rest is left to the template to handle
+        let Self {
+            metadata:
+                Metadata {
+                    artist,
+                    artists,
+                    title: Some(title),
+                    album,
+                    albumartist,
+                    length,
+                    rating,
+                    art,
+                    url,
+                    raw,
+                    ..
+                },
             playbackstatus: Some(playbackstatus),
+            position,
+            shuffle,
+            loop_status,
+            volume,
+            player,
+            identity,
+            ..
         } = self
+        else {
+            return None;
+        };
+
+        Some(template.render(|tag| {
+            match tag {
+                "artist" => artist.clone(),
+                "artists" => Some(artists.join(&options.artist_separator))
+                    .filter(|artists| !artists.is_empty()),
+                "title" => Some(title.clone()),
+                "status" => Some(status.to_string()),
+                "statuslabel" => match playbackstatus.as_str() {
+                    "Playing" => options.playing.clone(),
+                    _ => options.notplaying.clone(),
+                },
+                "position" => position.map(format_time),
+                "length" => length.map(format_time),
+                "progress" => progress_bar((*position)?, (*length)?, PROGRESS_WIDTH),
+                "album" => album.clone(),
+                "albumartist" => albumartist.clone(),
+                "rating" => rating.map(|rating| format_rating(rating, options.rating_style)),
+                "art" => art.clone(),
+                "url" => url.clone(),
+                "domain" => url.as_deref().and_then(domain),
+                "shuffle" => shuffle
+                    .filter(|shuffle| *shuffle)
+                    .map(|_| options.shuffle_icon.clone()),
+                "loop" => match loop_status.as_deref() {
+                    Some("Track") => Some(options.loop_track_icon.clone()),
+                    Some("Playlist") => Some(options.loop_playlist_icon.clone()),
+                    _ => None,
+                },
+                "volume" => volume.map(|volume| format!("{:.0}", volume.max(0.0) * 100.0)),
+                // The name the mediaplayer gives itself reads better than its bus name, when it has one
+                "player" => identity.clone().or_else(|| player.clone()),
+                _ => match tag.split_once(':') {
+                    Some(("progress", width)) => {
+                        progress_bar((*position)?, (*length)?, width.parse().ok()?)
+                    }
+                    Some(("meta", key)) => raw
+                        .get(key)
+                        .and_then(|value| metadata_text(value, &options.artist_separator)),
+                    _ => None,
+                },
+            }
+        }))
+    }
+
+    /// Send the media output, scrolled if enabled, returning whether there was anything to send
+    pub fn send(&self, options: &Arguments, sink: &Sink, scroll_offset: usize) -> bool {
+        // Construct the output from the user defined format
+        let Some(now_playing) = self.render(&self.format(options), options) else {
+            return false;
+        };
+
+        // A terminal has less room than a bar, so tmux is kept short unless told otherwise
+        let length = match options.output {
+            OutputMode::Tmux => options.length.or(Some(TMUX_LENGTH)),
+            _ => options.length,
+        };
+        let now_playing = if options.scroll {
+            scroll(&now_playing, options.scroll_width, scroll_offset)
+        } else {
+            match length {
+                Some(length) => truncate(now_playing, length, &options.ellipsis),
+                None => now_playing,
+            }
+        };
+
+        // Escaping comes last, so that truncating never cuts an escaped character in half
+        let now_playing = escape(now_playing, options.escape);
+
+        match options.output {
+            OutputMode::Waybar => self.send_waybar(options, sink, now_playing),
+            // Plain output is just the text, for bars that show each line as it is
+            OutputMode::Plain => sink.emit(now_playing),
+            OutputMode::I3bar => self.send_i3bar(options, sink, now_playing),
+            OutputMode::Yambar => sink.send_yambar(
+                &now_playing,
+                self.metadata.artist.as_deref().unwrap_or_default(),
+                self.metadata.title.as_deref().unwrap_or_default(),
+                self.playbackstatus.as_deref().unwrap_or_default(),
+            ),
+            OutputMode::Xsetroot => sink.set_root_name(&now_playing),
+            OutputMode::Tmux => sink.set_tmux_option(&now_playing),
+        }
+        true
+    }
+
+    /// What is playing as a JSON object, for consumers other than the bar
+    pub fn summary(&self, options: &Arguments) -> Value {
+        // The text is escaped as part of the JSON anyway
+        let escape_mode = match options.escape {
+            Escape::Json => Escape::None,
+            mode => mode,
+        };
+
+        json!({
+            "artist": self.metadata.artist,
+            "title": self.metadata.title,
+            "status": self.playbackstatus,
+            "player": self.player,
+            "text": self.render(&self.format(options), options).map(|text| escape(text, escape_mode)),
+        })
+    }
+
+    /// Send the media output as a block of the i3bar protocol
+    fn send_i3bar(&self, options: &Arguments, sink: &Sink, now_playing: String) {
+        let mut block = json!({
+            "name": "lizzy",
+            "full_text": now_playing,
+            "short_text": self.metadata.title,
+        });
+
+        if let Some(player) = &self.player {
+            block["instance"] = player.as_str().into();
+        }
+
+        if let Some(color) = &options.color {
+            block["color"] = color.as_str().into();
+        }
+
+        match serde_json::to_string(&block) {
+            Ok(json_string) => sink.emit(format!(",[{}]", json_string)),
+            Err(e) => error!("Failed to serialize JSON: {}", e),
+        }
+    }
+
+    /// Send the media output to Waybar as JSON
+    fn send_waybar(&self, options: &Arguments, sink: &Sink, now_playing: String) {
+        let mut output = json!({
+            "text": now_playing,
+            "alt": self.playbackstatus,
+            "class": self.playbackstatus,
+            "art": self.metadata.art,
+        });
+
+        // User defined classes are sent as an array, with the plain playbackstatus for {{status}}
+        let status = self.playbackstatus.as_deref().unwrap_or_default();
+        let mut class: Vec<String> = if options.class.is_empty() {
+            vec![status.to_string()]
+        } else {
+            options
+                .class
+                .iter()
+                .filter_map(|template| self.fill(template, options, status))
+                .filter(|class| !class.is_empty())
+                .collect()
+        };
+
+        // The mediaplayer is always a class as well, so that each one can be styled differently
+        if let Some(player) = self.player.as_deref().map(css_class) {
+            if !player.is_empty() && !class.contains(&player) {
+                class.push(player.clone());
+            }
+            output["player"] = player.into();
+        }
+
+        // What the mediaplayer is capable of, such as can-go-next, lets the bar grey out controls
+        class.extend(
+            self.capabilities
+                .iter()
+                .map(|capability| capability.to_string()),
+        );
+
+        output["class"] = class.into();
+
+        // Waybar only accepts a percentage when there is one
+        if let Some(percentage) = self.percentage() {
+            output["percentage"] = percentage.into();
+        }
+
+        // The tooltip is only included when asked for, otherwise Waybar shows its own
+        let mut tooltip: Vec<String> = options
+            .tooltip_format
+            .iter()
+            .filter_map(|tooltip_format| self.render(tooltip_format, options))
+            .collect();
+        if options.tooltip_metadata {
+            tooltip.push(self.metadata.dump(&options.artist_separator));
+        }
+        if options.tooltip_format.is_some() || options.tooltip_metadata {
+            output["tooltip"] = Some(tooltip.join("\n\n"))
+                .filter(|tooltip| !tooltip.is_empty())
+                .map(|tooltip| escape(tooltip, options.escape))
+                .into();
+        }
+
+        match serde_json::to_string(&output) {
+            Ok(json_string) => sink.emit(json_string),
+            Err(e) => error!("Failed to serialize JSON: {}", e),
+        }
+    }
+}
+
+/// Where the output goes, remembering the last output so that the same output is not sent again
+#[derive(Default)]
+pub struct Sink {
+    /// The last output that was sent
+    last_output: Mutex<Option<String>>,
+    /// Where the output goes instead of stdout, when writing to a FIFO
+    fifo: Option<watch::Sender<Option<String>>>,
+}
+
+impl Sink {
+    /// Send the output to a FIFO instead of stdout
+    pub fn to_fifo(fifo: watch::Sender<Option<String>>) -> Self {
+        Sink {
+            fifo: Some(fifo),
+            ..Sink::default()
+        }
+    }
+
+    /// Check if the output is the same as the last one, remembering it for the next time
+    fn is_repeated(&self, output: &str) -> bool {
+        let mut last_output = self.last_output.lock().expect("Last output lock poisoned");
+        if last_output.as_deref() == Some(output) {
+            return true;
+        }
+        *last_output = Some(output.to_string());
+        false
+    }
+
+    /// Forget the last output, so that the next one is sent even if it is the same
+    pub fn forget_last_output(&self) {
+        *self.last_output.lock().expect("Last output lock poisoned") = None;
+    }
+
+    /// Print the output, unless it is the same as the last one since mediaplayers often send the same thing several times
+    fn emit(&self, output: String) {
+        if !self.is_repeated(&output) {
+            match &self.fifo {
+                Some(fifo) => {
+                    fifo.send_replace(Some(output));
+                }
+                None => println!("{}", output),
+            }
+        }
+    }
+
+    /// Send the tags of a yambar script module, ending the update with an empty line
+    fn send_yambar(&self, text: &str, artist: &str, title: &str, status: &str) {
+        // Every tag is a line of its own, so the values can't span several lines
+        let tags: String = [
+            ("text", text),
+            ("artist", artist),
+            ("title", title),
+            ("status", status),
+        ]
+        .iter()
+        .map(|(tag, value)| format!("{}|string|{}\n", tag, value.replace('\n', " ")))
+        .collect();
+        self.emit(tags);
+    }
+
+    /// Set the name of the X11 root window, which is what dwm shows in its bar
+    fn set_root_name(&self, text: &str) {
+        if self.is_repeated(text) {
+            return;
+        }
+
+        if let Err(err) = Command::new("xsetroot").arg("-name").arg(text).spawn() {
+            error!("Failed to run xsetroot: {}", err);
+        }
+    }
+
+    /// Set the @lizzy option of tmux, to be shown with #{@lizzy} in the status line
+    fn set_tmux_option(&self, text: &str) {
+        if self.is_repeated(text) {
+            return;
+        }
+
+        // A line break in the status line would break the terminal layout
+        if let Err(err) = Command::new("tmux")
+            .args(["set-option", "-gq", "@lizzy"])
+            .arg(text.replace(['\n', '\t'], " "))
+            .spawn()
         {
-            // Construct the output from user defined format and escape ampersands
-            let now_playing = output_format
-                .replace("{{artist}}", artist)
-                .replace("{{title}}", title);
+            error!("Failed to run tmux: {}", err);
+        }
+    }
 
-            match serde_json::to_string(&json!({
-                "text": now_playing,
-                "alt": playbackstatus,
-                "class": playbackstatus,
-            })) {
-                Ok(json_string) => println!("{}", json_string),
-                Err(e) => eprintln!("Failed to serialize JSON: {}", e),
+    /// Print what has to come before any output, unless the FIFO sends it to every reader itself
+    pub fn header(&self, options: &Arguments) {
+        if self.fifo.is_none() {
+            for line in header_lines(options) {
+                println!("{}", line);
             }
         }
     }
+
+    /// Send every mediaplayer as a JSON array, for widgets that show a list rather than a single line
+    pub fn send_all(&self, options: &Arguments, media: &[Media]) {
+        let players: Vec<_> = media.iter().map(|media| media.summary(options)).collect();
+
+        match serde_json::to_string(&players) {
+            Ok(json_string) => self.emit(json_string),
+            Err(e) => error!("Failed to serialize JSON: {}", e),
+        }
+    }
+
+    /// Clear the output when there is no media to show, or show the idle text if there is one
+    pub fn clear(&self, options: &Arguments) {
+        let idle_text = options.idle_text.as_deref().unwrap_or_default();
+
+        match options.output {
+            OutputMode::Waybar if !idle_text.is_empty() => {
+                self.emit(json!({ "text": idle_text, "alt": "idle", "class": "idle" }).to_string())
+            }
+            // An empty line hides the module in Waybar and empties the text of other bars
+            OutputMode::Waybar | OutputMode::Plain => self.emit(idle_text.to_string()),
+            OutputMode::I3bar if !idle_text.is_empty() => self.emit(format!(
+                ",[{}]",
+                json!({ "name": "lizzy", "full_text": idle_text })
+            )),
+            OutputMode::I3bar => self.emit(String::from(",[]")),
+            OutputMode::Yambar => self.send_yambar(idle_text, "", "", ""),
+            OutputMode::Xsetroot => self.set_root_name(idle_text),
+            OutputMode::Tmux => self.set_tmux_option(idle_text),
+        }
+    }
+}
+
+/// The lines that have to come before any output, such as the i3bar protocol header
+pub fn header_lines(options: &Arguments) -> Vec<String> {
+    match options.output {
+        // The status lines are an infinite array, started with an empty one so that every update can begin with a comma
+        OutputMode::I3bar => vec![
+            json!({ "version": 1 }).to_string(),
+            String::from("["),
+            String::from("[]"),
+        ],
+        _ => Vec::new(),
+    }
+}
+
+/// What is shared when nothing is playing, which is only the status
+pub fn stopped() -> String {
+    json!({ "status": "Stopped" }).to_string()
+}
+
+/// Pass what is playing on as JSON to something other than the bar, unless it is the same as what it has
+pub fn share(sender: &watch::Sender<Option<String>>, options: &Arguments, media: Option<&Media>) {
+    let summary = media.map_or_else(stopped, |media| media.summary(options).to_string());
+    sender.send_if_modified(|current| {
+        let changed = current.as_ref() != Some(&summary);
+        if changed {
+            *current = Some(summary);
+        }
+        changed
+    });
+}
+
+/// What is playing as shared last, or that nothing is before anything has been output
+pub fn latest(receiver: &mut watch::Receiver<Option<String>>) -> String {
+    receiver.borrow_and_update().clone().unwrap_or_else(stopped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn truncates_to_display_columns() {
+        assert_eq!(truncate(String::from("Artist"), 6, "…"), "Artist");
+        assert_eq!(truncate(String::from("Artist - Song"), 8, "…"), "Artist …");
+        assert_eq!(
+            truncate(String::from("Artist - Song"), 8, "..."),
+            "Artis..."
+        );
+        // Wide characters take two columns each
+        assert_eq!(truncate(String::from("日本語の歌"), 5, "…"), "日本…");
+        assert_eq!(truncate(String::from("日本語の歌"), 10, "…"), "日本語の歌");
+    }
+
+    #[test]
+    fn scrolls_around_to_the_beginning() {
+        assert_eq!(scroll("short", 5, 3), "short");
+        assert_eq!(scroll("abcdef", 4, 0), "abcd");
+        assert_eq!(scroll("abcdef", 4, 5), "f   ");
+        assert_eq!(scroll("abcdef", 4, 7), "  ab");
+        assert_eq!(scroll("abcdef", 4, 9), "abcd");
+        // A wide character that doesn't fit is left out rather than cut in half
+        assert_eq!(scroll("日本語の歌", 5, 0), "日本");
+        assert_eq!(scroll("日本語の歌", 5, 1), "本語");
+    }
+
+    #[test]
+    fn fills_the_progress_bar() {
+        assert_eq!(progress_bar(0, 100, 4).as_deref(), Some("▱▱▱▱"));
+        assert_eq!(progress_bar(50, 100, 10).as_deref(), Some("▰▰▰▰▰▱▱▱▱▱"));
+        assert_eq!(progress_bar(1, 3, 10).as_deref(), Some("▰▰▰▱▱▱▱▱▱▱"));
+        // A position outside of the track is kept within the bar
+        assert_eq!(progress_bar(150, 100, 4).as_deref(), Some("▰▰▰▰"));
+        assert_eq!(progress_bar(-5, 100, 4).as_deref(), Some("▱▱▱▱"));
+        assert_eq!(progress_bar(10, 0, 4), None);
+    }
+
+    #[test]
+    fn follows_the_position_in_percent() {
+        let metadata = Metadata {
+            length: Some(200),
+            ..Default::default()
+        };
+        let mut media = Media::new(
+            metadata,
+            Some(String::from("Playing")),
+            Some(50),
+            None,
+            None,
+            None,
+            None,
+        );
+        assert_eq!(media.percentage(), Some(25));
+        assert!(media.progressing());
+
+        // The clock moves the position on, and the percentage with it
+        media.position = Some(150);
+        assert_eq!(media.percentage(), Some(75));
+        media.position = Some(250);
+        assert_eq!(media.percentage(), Some(100));
+
+        // Even a length far too long to be true is taken a percentage of
+        media.metadata.length = Some(i64::MAX);
+        media.position = Some(i64::MAX / 2);
+        assert_eq!(media.percentage(), Some(49));
+
+        media.playbackstatus = Some(String::from("Paused"));
+        assert!(!media.progressing());
+
+        // Without a length there is nothing to take a percentage of
+        media.playbackstatus = Some(String::from("Playing"));
+        media.metadata.length = None;
+        assert_eq!(media.percentage(), None);
+        assert!(!media.progressing());
+    }
 }
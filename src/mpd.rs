@@ -0,0 +1,192 @@
+use crate::media::{Media, Metadata};
+use crate::options::Arguments;
+use crate::BoxedError;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader, Lines};
+use tokio::net::{TcpStream, UnixStream};
+
+/// The subsystems of MPD whose changes affect the output
+const SUBSYSTEMS: &str = "player mixer options";
+
+/// A connection to MPD speaking its own protocol, for when MPD is used without an MPRIS bridge
+pub struct Client {
+    reader: Lines<BufReader<Box<dyn AsyncRead + Unpin + Send>>>,
+    writer: Box<dyn AsyncWrite + Unpin + Send>,
+}
+
+/// Where MPD listens and its password if any, given the same way as to mpc with MPD_HOST and MPD_PORT
+fn address() -> (Option<String>, String) {
+    let host = std::env::var("MPD_HOST").unwrap_or_else(|_| String::from("localhost"));
+    let port = std::env::var("MPD_PORT").unwrap_or_else(|_| String::from("6600"));
+
+    // A password comes first, as in password@host
+    let (password, host) = match host.rsplit_once('@') {
+        Some((password, host)) if !password.is_empty() => (Some(password.to_string()), host),
+        _ => (None, host.as_str()),
+    };
+
+    // An absolute path is a Unix socket
+    if host.starts_with('/') {
+        (password, host.to_string())
+    } else {
+        (password, format!("{}:{}", host, port))
+    }
+}
+
+/// Quote an argument of a command, as MPD splits them on spaces otherwise
+fn quote(argument: &str) -> String {
+    format!(
+        "\"{}\"",
+        argument.replace('\\', "\\\\").replace('"', "\\\"")
+    )
+}
+
+impl Client {
+    /// Connect to MPD and log in if a password is set
+    pub async fn connect() -> Result<Self, BoxedError> {
+        let (password, address) = address();
+
+        let (reader, writer): (
+            Box<dyn AsyncRead + Unpin + Send>,
+            Box<dyn AsyncWrite + Unpin + Send>,
+        ) = if address.starts_with('/') {
+            let (reader, writer) = UnixStream::connect(&address).await?.into_split();
+            (Box::new(reader), Box::new(writer))
+        } else {
+            let (reader, writer) = TcpStream::connect(&address).await?.into_split();
+            (Box::new(reader), Box::new(writer))
+        };
+
+        let mut client = Client {
+            reader: BufReader::new(reader).lines(),
+            writer,
+        };
+
+        // MPD greets with its version first, such as "OK MPD 0.23.5"
+        match client.reader.next_line().await? {
+            Some(greeting) if greeting.starts_with("OK MPD ") => {}
+            _ => return Err(format!("{} is not MPD", address).into()),
+        }
+
+        if let Some(password) = password {
+            client
+                .command(&format!("password {}", quote(&password)))
+                .await?;
+        }
+
+        Ok(client)
+    }
+
+    /// Send a command and collect the pairs of its response until MPD says OK
+    async fn command(&mut self, command: &str) -> Result<Vec<(String, String)>, BoxedError> {
+        self.writer
+            .write_all(format!("{}\n", command).as_bytes())
+            .await?;
+
+        let mut pairs = Vec::new();
+        loop {
+            let line = self
+                .reader
+                .next_line()
+                .await?
+                .ok_or("MPD closed the connection")?;
+
+            if line == "OK" {
+                return Ok(pairs);
+            }
+            if let Some(err) = line.strip_prefix("ACK ") {
+                return Err(format!("MPD refused '{}': {}", command, err).into());
+            }
+            if let Some((key, value)) = line.split_once(": ") {
+                pairs.push((key.to_string(), value.to_string()));
+            }
+        }
+    }
+
+    /// Wait until something changes that affects the output
+    pub async fn idle(&mut self) -> Result<(), BoxedError> {
+        self.command(&format!("idle {}", SUBSYSTEMS)).await?;
+        Ok(())
+    }
+
+    /// Get the current media, or none if nothing is queued
+    pub async fn media(&mut self, options: &Arguments) -> Result<Option<Media>, BoxedError> {
+        let status = self.command("status").await?;
+        let song = self.command("currentsong").await?;
+
+        if song.is_empty() {
+            return Ok(None);
+        }
+
+        let get = |pairs: &[(String, String)], key: &str| {
+            pairs
+                .iter()
+                .find(|(name, _)| name == key)
+                .map(|(_, value)| value.clone())
+        };
+        let seconds = |value: Option<String>| {
+            value
+                .and_then(|value| value.parse::<f64>().ok())
+                .map(|seconds| (seconds * 1_000_000.0) as i64)
+        };
+        let flag = |key: &str| get(&status, key).map(|value| value == "1");
+
+        let artists: Vec<String> = song
+            .iter()
+            .filter(|(name, _)| name == "Artist")
+            .map(|(_, value)| value.clone())
+            .collect();
+
+        let mut metadata = Metadata {
+            artist: artists.first().cloned(),
+            artists,
+            // Files without tags have no title, so show the name of the file as mpd-mpris does
+            title: get(&song, "Title").or_else(|| {
+                get(&song, "file")
+                    .map(|file| file.rsplit('/').next().unwrap_or_default().to_string())
+            }),
+            album: get(&song, "Album"),
+            albumartist: get(&song, "AlbumArtist"),
+            length: seconds(get(&song, "duration").or_else(|| get(&song, "Time"))),
+            trackid: get(&song, "Id"),
+            // Only streams have a url, files are just a path in the music directory
+            url: get(&song, "file").filter(|file| file.contains("://")),
+            ..Metadata::default()
+        };
+        metadata.clean(options);
+
+        let playbackstatus = match get(&status, "state").as_deref() {
+            Some("play") => "Playing",
+            Some("pause") => "Paused",
+            _ => "Stopped",
+        };
+
+        // Repeating a single song is what MPRIS calls looping the track
+        let loop_status = match (flag("repeat"), flag("single")) {
+            (Some(true), Some(true)) => "Track",
+            (Some(true), _) => "Playlist",
+            _ => "None",
+        };
+
+        // MPD reports -1 when it has no control over the volume
+        let volume = get(&status, "volume")
+            .and_then(|volume| volume.parse::<f64>().ok())
+            .filter(|volume| *volume >= 0.0)
+            .map(|volume| volume / 100.0);
+
+        let mut media = Media::new(
+            metadata,
+            Some(playbackstatus.to_string()),
+            seconds(get(&status, "elapsed")),
+            flag("random"),
+            Some(loop_status.to_string()),
+            volume,
+            Some(String::from("mpd")),
+        );
+        media.capabilities = crate::CAPABILITIES
+            .iter()
+            .map(|(_, class)| *class)
+            .collect();
+
+        Ok(Some(media))
+    }
+}
@@ -0,0 +1,218 @@
+use crate::config::Mqtt;
+use crate::media;
+use crate::BoxedError;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::watch;
+use tracing::{debug, warn};
+
+/// The packet types of MQTT 3.1.1, shifted into the upper half of the first byte
+const CONNECT: u8 = 0x10;
+const CONNACK: u8 = 0x20;
+const PUBLISH: u8 = 0x30;
+const PINGREQ: u8 = 0xc0;
+
+/// Publishes are retained so that dashboards know what is playing as soon as they subscribe
+const RETAIN: u8 = 0x01;
+
+/// How long the broker waits for a sign of life before it gives up on lizzy, which pings at half of it
+const KEEP_ALIVE: Duration = Duration::from_secs(60);
+
+/// How long to wait before connecting to the broker again, doubled after each failed attempt
+const RECONNECT_BACKOFF_MIN: Duration = Duration::from_secs(1);
+const RECONNECT_BACKOFF_MAX: Duration = Duration::from_secs(60);
+
+/// Encode a string the way MQTT does, prefixed with its length
+fn string(bytes: &mut Vec<u8>, value: &str) {
+    bytes.extend_from_slice(&(value.len() as u16).to_be_bytes());
+    bytes.extend_from_slice(value.as_bytes());
+}
+
+/// Put together a packet, whose length is written in 7 bit groups after its type
+fn packet(header: u8, body: &[u8]) -> Vec<u8> {
+    let mut packet = vec![header];
+    let mut length = body.len();
+    loop {
+        let mut byte = (length % 128) as u8;
+        length /= 128;
+        if length > 0 {
+            byte |= 0x80;
+        }
+        packet.push(byte);
+        if length == 0 {
+            break;
+        }
+    }
+    packet.extend_from_slice(body);
+    packet
+}
+
+/// A connection to the MQTT broker
+struct Client {
+    stream: TcpStream,
+}
+
+impl Client {
+    async fn connect(mqtt: &Mqtt) -> Result<Self, BoxedError> {
+        let mut stream = TcpStream::connect((mqtt.host.as_str(), mqtt.port)).await?;
+
+        // A clean session with a retained last will, so that the broker marks lizzy as stopped when it goes away
+        let mut flags = 0x02 | 0x04 | 0x20;
+        if mqtt.username.is_some() {
+            flags |= 0x80;
+        }
+        if mqtt.password.is_some() {
+            flags |= 0x40;
+        }
+
+        let mut body = Vec::new();
+        string(&mut body, "MQTT");
+        body.push(4);
+        body.push(flags);
+        body.extend_from_slice(&(KEEP_ALIVE.as_secs() as u16).to_be_bytes());
+        string(&mut body, &format!("lizzy-{}", std::process::id()));
+        string(&mut body, &mqtt.topic);
+        string(&mut body, &media::stopped());
+        if let Some(username) = &mqtt.username {
+            string(&mut body, username);
+        }
+        if let Some(password) = &mqtt.password {
+            string(&mut body, password);
+        }
+        stream.write_all(&packet(CONNECT, &body)).await?;
+
+        // The broker acknowledges with a return code, where anything but zero means it refused
+        let mut connack = [0; 4];
+        stream.read_exact(&mut connack).await?;
+        if connack[0] != CONNACK {
+            return Err(format!("{}:{} is not an MQTT broker", mqtt.host, mqtt.port).into());
+        }
+        if connack[3] != 0 {
+            return Err(format!(
+                "The MQTT broker refused the connection with code {}",
+                connack[3]
+            )
+            .into());
+        }
+
+        Ok(Client { stream })
+    }
+
+    async fn publish(&mut self, topic: &str, payload: &str) -> Result<(), BoxedError> {
+        let mut body = Vec::new();
+        string(&mut body, topic);
+        body.extend_from_slice(payload.as_bytes());
+        self.stream
+            .write_all(&packet(PUBLISH | RETAIN, &body))
+            .await?;
+        Ok(())
+    }
+
+    async fn ping(&mut self) -> Result<(), BoxedError> {
+        self.stream.write_all(&packet(PINGREQ, &[])).await?;
+        Ok(())
+    }
+
+    /// Read whatever the broker sends, which is only answers to pings, until it closes the connection
+    async fn closed(&mut self) -> Result<(), BoxedError> {
+        let mut buffer = [0; 64];
+        loop {
+            if self.stream.read(&mut buffer).await? == 0 {
+                return Err("The MQTT broker closed the connection".into());
+            }
+        }
+    }
+}
+
+/// Publish the payload whenever it changes, connecting to the broker when it is needed
+///
+/// The latest payload is published again whenever the connection is back, since the broker
+/// published the last will of lizzy when it was lost.
+pub async fn publish(mqtt: Mqtt, mut payloads: watch::Receiver<Option<String>>) {
+    let mut client: Option<Client> = None;
+    let mut backoff = RECONNECT_BACKOFF_MIN;
+    // Whether the latest payload has yet to reach the broker
+    let mut pending = false;
+
+    loop {
+        match &mut client {
+            // Keep the connection alive between changes, and notice when the broker goes away
+            Some(connected) if !pending => {
+                tokio::select! {
+                    changed = payloads.changed() => {
+                        if changed.is_err() {
+                            return;
+                        }
+                        pending = true;
+                    }
+                    _ = tokio::time::sleep(KEEP_ALIVE / 2) => {
+                        if let Err(err) = connected.ping().await {
+                            debug!(event = "mqtt", "Failed to ping the MQTT broker: {}", err);
+                            client = None;
+                            pending = true;
+                        }
+                        continue;
+                    }
+                    Err(err) = connected.closed() => {
+                        debug!(event = "mqtt", "Lost the MQTT broker: {}", err);
+                        client = None;
+                        pending = true;
+                        continue;
+                    }
+                }
+            }
+            Some(_) => {}
+            // The broker may have been started or restarted since the last time, so connect again
+            None if pending => match Client::connect(&mqtt).await {
+                Ok(connected) => {
+                    client = Some(connected);
+                    backoff = RECONNECT_BACKOFF_MIN;
+                }
+                Err(err) => {
+                    warn!(
+                        event = "mqtt",
+                        "Failed to connect to the MQTT broker, retrying in {} seconds: {}",
+                        backoff.as_secs(),
+                        err
+                    );
+                    tokio::select! {
+                        _ = tokio::time::sleep(backoff) => {}
+                        changed = payloads.changed() => {
+                            if changed.is_err() {
+                                return;
+                            }
+                        }
+                    }
+                    backoff = (backoff * 2).min(RECONNECT_BACKOFF_MAX);
+                    continue;
+                }
+            },
+            None => {
+                if payloads.changed().await.is_err() {
+                    return;
+                }
+                pending = true;
+                continue;
+            }
+        }
+
+        let Some(payload) = payloads.borrow_and_update().clone() else {
+            pending = false;
+            continue;
+        };
+
+        if let Some(connected) = &mut client {
+            match connected.publish(&mqtt.topic, &payload).await {
+                Ok(()) => pending = false,
+                Err(err) => {
+                    warn!(
+                        event = "mqtt",
+                        "Failed to publish to the MQTT broker: {}", err
+                    );
+                    client = None;
+                }
+            }
+        }
+    }
+}
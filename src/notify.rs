@@ -0,0 +1,55 @@
+use crate::media::Media;
+use crate::options::Arguments;
+use crate::BoxedError;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU32, Ordering};
+use zbus::zvariant::Value;
+use zbus::Connection;
+
+/// Show the media in a desktop notification, replacing the previous one sent by lizzy
+pub async fn send(
+    connection: &Connection,
+    options: &Arguments,
+    notification_id: &AtomicU32,
+    media: &Media,
+) -> Result<(), BoxedError> {
+    // The title is the summary, the body is rendered from the same kind of template as the output
+    let (Some(summary), Some(body)) = (
+        media.metadata.title.as_deref(),
+        media.render(&options.notify_format, options),
+    ) else {
+        return Ok(());
+    };
+
+    let actions: Vec<&str> = Vec::new();
+
+    // The album art is shown as the image of the notification, when it has been resolved
+    let mut hints: HashMap<&str, Value> = HashMap::new();
+    if let Some(art) = media.metadata.art.as_deref() {
+        hints.insert("image-path", Value::from(art));
+    }
+
+    let reply = connection
+        .call_method(
+            Some("org.freedesktop.Notifications"),
+            "/org/freedesktop/Notifications",
+            Some("org.freedesktop.Notifications"),
+            "Notify",
+            &(
+                "lizzy",
+                notification_id.load(Ordering::Relaxed),
+                "",
+                summary,
+                body,
+                actions,
+                hints,
+                -1,
+            ),
+        )
+        .await?;
+
+    // Keep the id so that the next notification replaces this one instead of piling up
+    notification_id.store(reply.body().deserialize::<u32>()?, Ordering::Relaxed);
+
+    Ok(())
+}
@@ -1,21 +1,397 @@
-const HELP: &str = r#"lizzy
+use crate::commands::Command;
+use crate::config::{self, Config};
+use crate::matcher::Pattern;
+use crate::template::Template;
+use once_cell::sync::Lazy;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+
+/// The help, which the completions and the man page are also generated from
+pub const HELP: &str = r#"lizzy
 =====
 
 USAGE:
-  lizzy --[OPTIONS] [INPUT]
+  lizzy [COMMAND] --[OPTIONS]
+COMMANDS:
+  players               List the running mediaplayers with their identity and status
+  play-pause            Toggle playback of the mediaplayer
+  next                  Skip to the next track of the mediaplayer
+  previous              Skip to the previous track of the mediaplayer
+  stop                  Stop playback of the mediaplayer
+  shuffle               Turn shuffle of the mediaplayer on or off
+  loop [STATUS]         Set the loop status to None, Track or Playlist,
+                        or go to the next one
+  volume CHANGE         Change the volume by such as +5 or -5 percent,
+                        or set it to such as 40 percent
+  seek OFFSET           Seek by such as +10s or -1:00
+  position TIME         Go to a position in the track, such as 1:23
+  raise                 Bring the window of the mediaplayer to the front
+  quit                  Quit the mediaplayer
+  launch [NAME]         Start the mediaplayer if it is not running
+  control REQUEST       Send a request to a lizzy running with --control:
+                        refresh, play-pause, next, previous, stop or switch [NAME]
+  completions SHELL     Print completions for bash, zsh or fish
+  man                   Print the man page
 FLAGS:
   -h, --help            Prints help information
 OPTIONS:
+  --output MODE         waybar, plain, i3bar, yambar, xsetroot or tmux  <Default: "waybar">
+  --backend BACKEND     mpris, or mpd or cmus to ask them without MPRIS <Default: "mpris">
+  --color COLOR         Text color of the i3bar block, such as #ffffff  <Default: None>
+  --escape MODE         none, ampersand, pango or json                  <Default: "none">
   --format STRING       The format of output using handlebar tags       <Default: "{{artist}} - {{title}}">
-  --mediaplayer STRING  Mediaplayer interface to pick up signals from   <Default: None>
+  --format-paused STRING The format of output while paused              <Default: None>
+  --format-stopped STRING The format of output while stopped            <Default: None>
+  --idle-text STRING    Text to show when nothing is playing            <Default: None>
+  --length NUMBER       Truncate the output to a number of columns      <Default: None>
+  --ellipsis STRING     Marks that the output was truncated             <Default: "…">
+  --scroll              Scroll output that is wider than the width      <Default: False>
+  --scroll-width NUMBER The number of columns to scroll through         <Default: 30>
+  --scroll-speed MS     Milliseconds between each step of scrolling     <Default: 500>
+  --tooltip-format STRING The format of the tooltip                     <Default: None>
+  --art                 Resolve album art for {{art}} and the art field <Default: False>
+  --art-lookup          Look up missing art on MusicBrainz, with --art  <Default: False>
+  --tooltip-metadata    List all metadata of the track in the tooltip   <Default: False>
+  --class STRING        Comma separated classes using handlebar tags    <Default: "{{status}}">
+  --mediaplayer STRING  Comma separated mediaplayers, in priority order <Default: None>
+  --identity STRING     Mediaplayers by Identity, such as Spotify       <Default: None>
+  --interval SECONDS    Re-render the output every N seconds            <Default: None>
+  --ignore STRING       Mediaplayers to ignore, repeatable or a list    <Default: None>
+  --keep-mirrors        Show mirrors of mediaplayers, as of KDE Connect <Default: False>
+  --match-case-insensitive Ignore casing when matching mediaplayers     <Default: False>
+  --follow              Display the most recently active mediaplayer    <Default: False>
+  --all-players         Output all mediaplayers as a JSON array         <Default: False>
+  --once                Print the current output once and exit          <Default: False>
+  --hide-paused-after SECONDS Hide the output when paused for a while   <Default: None>
+  --launch-on-click     Start the mediaplayer on a command if closed    <Default: False>
   --autotoggle          Include this flag for automatic play/pause      <Default: False>
+  --autotoggle-cooldown MS Least time between toggles of a mediaplayer  <Default: 1000>
+  --autotoggle-ignore STRING Mediaplayers that never trigger autotoggle <Default: None>
+  --autotoggle-min-length SECONDS Only autotoggle for longer media      <Default: None>
+  --autotoggle-audible  Only autotoggle for mediaplayers making sound   <Default: False>
+  --pause-others        Pause all others when the mediaplayer plays     <Default: False>
+  --pause-on-lock       Pause the mediaplayer when the session locks    <Default: False>
+  --resume-on-unlock    Resume what --pause-on-lock paused on unlock    <Default: False>
+  --pause-on-suspend    Pause the mediaplayer before suspending         <Default: False>
+  --resume-on-wake      Resume what --pause-on-suspend paused on wake   <Default: False>
+  --pause-on-microphone Pause the mediaplayer while a microphone is on  <Default: False>
+  --inhibit-idle        Keep the screen from locking while playing      <Default: False>
+  --artist-separator STRING Joins the artists of {{artists}}            <Default: ", ">
+  --rating-style STYLE  {{rating}} as stars or a number out of five     <Default: "stars">
+  --playing-icon STRING Replaces {{status}} while playing               <Default: "Playing">
+  --paused-icon STRING  Replaces {{status}} while paused                <Default: "Paused">
+  --playing STRING      {{statuslabel}} while playing                   <Default: None>
+  --notplaying STRING   {{statuslabel}} while paused or stopped         <Default: None>
+  --shuffle-icon STRING {{shuffle}} while shuffling                     <Default: "Shuffle">
+  --loop-track-icon STRING {{loop}} while repeating the track           <Default: "Track">
+  --loop-playlist-icon STRING {{loop}} while repeating the playlist     <Default: "Playlist">
+  --on-change COMMAND   Run a command whenever the track changes        <Default: None>
+  --fifo PATH           Write the output to a FIFO instead of stdout    <Default: None>
+  --output-file PATH    Also write the output to a file, as for OBS     <Default: None>
+  --output-file-format STRING The format of the output file             <Default: None>
+  --signal NUMBER       Send Waybar SIGRTMIN+NUMBER when output changes <Default: None>
+  --notify              Send a desktop notification on track change     <Default: False>
+  --discord-client-id ID Show what plays as Rich Presence on Discord    <Default: None>
+  --notify-format STRING The format of the notification body            <Default: "{{artist}}">
+  --dbus-service        Publish the displayed media as se.stefur.lizzy  <Default: False>
+  --serve ADDRESS       Serve what plays over HTTP and WebSocket        <Default: None>
+  --serve-origin ORIGIN Web pages let in by --serve, repeatable or *    <Default: None>
+  --event-socket PATH   Stream what plays as JSON lines on a socket     <Default: None>
+  --control             Listen for requests on a control socket         <Default: False>
+  --socket PATH         Path of the control socket                      <Default: "$XDG_RUNTIME_DIR/lizzy.sock">
+  --log-level LEVEL     error, warn, info, debug or trace               <Default: "warn">
+  --log-file PATH       Write logs to a file instead of stderr          <Default: None>
+  --log-journald        Write logs to the systemd journal as well       <Default: False>
+  --history             Record listened tracks in an SQLite database    <Default: False>
+  --clean-titles        Strip noise like (Official Video) from titles   <Default: False>
+  --config PATH         Path of the config file                         <Default: "$XDG_CONFIG_HOME/lizzy/config.toml">
 "#;
 
+/// The format of output when neither the options nor the config file have one
+static DEFAULT_FORMAT: Lazy<Template> = Lazy::new(|| {
+    "{{artist}} - {{title}}"
+        .parse()
+        .expect("Default format is invalid")
+});
+
+/// What kind of bar or consumer the output is meant for
+pub enum OutputMode {
+    Waybar,
+    Plain,
+    I3bar,
+    Yambar,
+    Xsetroot,
+    Tmux,
+}
+
+impl std::str::FromStr for OutputMode {
+    type Err = String;
+
+    fn from_str(mode: &str) -> Result<Self, Self::Err> {
+        match mode {
+            "waybar" => Ok(OutputMode::Waybar),
+            "plain" => Ok(OutputMode::Plain),
+            "i3bar" => Ok(OutputMode::I3bar),
+            "yambar" => Ok(OutputMode::Yambar),
+            "xsetroot" => Ok(OutputMode::Xsetroot),
+            "tmux" => Ok(OutputMode::Tmux),
+            _ => Err(format!("unknown output mode '{}'", mode)),
+        }
+    }
+}
+
+/// Where lizzy gets the media from
+pub enum Backend {
+    Mpris,
+    Mpd,
+    Cmus,
+}
+
+impl std::str::FromStr for Backend {
+    type Err = String;
+
+    fn from_str(backend: &str) -> Result<Self, Self::Err> {
+        match backend {
+            "mpris" => Ok(Backend::Mpris),
+            "mpd" => Ok(Backend::Mpd),
+            "cmus" => Ok(Backend::Cmus),
+            _ => Err(format!("unknown backend '{}'", backend)),
+        }
+    }
+}
+
+/// How to escape the text for whatever shows it
+#[derive(Clone, Copy)]
+pub enum Escape {
+    None,
+    Ampersand,
+    Pango,
+    Json,
+}
+
+impl std::str::FromStr for Escape {
+    type Err = String;
+
+    fn from_str(mode: &str) -> Result<Self, Self::Err> {
+        match mode {
+            "none" => Ok(Escape::None),
+            "ampersand" => Ok(Escape::Ampersand),
+            "pango" => Ok(Escape::Pango),
+            "json" => Ok(Escape::Json),
+            _ => Err(format!("unknown escape mode '{}'", mode)),
+        }
+    }
+}
+
+/// How to show the rating of a track
+#[derive(Clone, Copy)]
+pub enum RatingStyle {
+    Stars,
+    Number,
+}
+
+impl std::str::FromStr for RatingStyle {
+    type Err = String;
+
+    fn from_str(style: &str) -> Result<Self, Self::Err> {
+        match style {
+            "stars" => Ok(RatingStyle::Stars),
+            "number" => Ok(RatingStyle::Number),
+            _ => Err(format!("unknown rating style '{}'", style)),
+        }
+    }
+}
+
 pub struct Arguments {
-    pub format: String,
-    pub mediaplayer: String,
+    pub command: Option<Command>,
+    pub output: OutputMode,
+    pub backend: Backend,
+    pub color: Option<String>,
+    pub escape: Escape,
+    pub format: Option<Template>,
+    pub format_paused: Option<Template>,
+    pub format_stopped: Option<Template>,
+    pub idle_text: Option<String>,
+    pub length: Option<usize>,
+    pub ellipsis: String,
+    pub scroll: bool,
+    pub scroll_width: usize,
+    pub scroll_speed: u64,
+    pub tooltip_format: Option<Template>,
+    pub tooltip_metadata: bool,
+    pub art: bool,
+    pub art_lookup: bool,
+    pub class: Vec<Template>,
+    pub mediaplayers: Vec<Pattern>,
+    pub identities: Vec<Pattern>,
+    pub ignore: Vec<Pattern>,
+    pub keep_mirrors: bool,
+    pub follow: bool,
+    pub once: bool,
+    pub hide_paused_after: Option<u64>,
+    pub launch_on_click: bool,
     pub autotoggle: bool,
-    pub glob: bool,
+    pub autotoggle_cooldown: u64,
+    pub autotoggle_ignore: Vec<Pattern>,
+    pub autotoggle_min_length: Option<u64>,
+    pub autotoggle_audible: bool,
+    pub pause_others: bool,
+    pub all_players: bool,
+    pub discord_client_id: Option<String>,
+    pub pause_on_lock: bool,
+    pub resume_on_unlock: bool,
+    pub pause_on_suspend: bool,
+    pub resume_on_wake: bool,
+    pub inhibit_idle: bool,
+    pub pause_on_microphone: bool,
+    pub interval: Option<u64>,
+    pub artist_separator: String,
+    pub rating_style: RatingStyle,
+    pub playing_icon: Option<String>,
+    pub paused_icon: Option<String>,
+    pub playing: Option<String>,
+    pub notplaying: Option<String>,
+    pub shuffle_icon: String,
+    pub loop_track_icon: String,
+    pub loop_playlist_icon: String,
+    pub fifo: Option<PathBuf>,
+    pub output_file: Option<PathBuf>,
+    pub output_file_format: Option<Template>,
+    pub signal: Option<u8>,
+    pub on_change: Option<String>,
+    pub notify: bool,
+    pub notify_format: Template,
+    pub dbus_service: bool,
+    pub serve: Option<SocketAddr>,
+    pub serve_origin: Vec<String>,
+    pub event_socket: Option<PathBuf>,
+    pub control: bool,
+    pub socket: Option<PathBuf>,
+    pub log_level: tracing::Level,
+    pub log_file: Option<PathBuf>,
+    pub log_journald: bool,
+    pub history: bool,
+    pub clean_titles: bool,
+    pub match_case_insensitive: bool,
+    pub config_path: Option<PathBuf>,
+    pub config: RwLock<Arc<Config>>,
+}
+
+impl Arguments {
+    /// The config file as it was last read
+    pub fn config(&self) -> Arc<Config> {
+        self.config.read().expect("Config lock poisoned").clone()
+    }
+
+    /// Read the config file again, keeping the one read before if the new one is invalid
+    pub fn reload_config(&self) -> Result<(), String> {
+        let config = config::load(self.config_path.as_deref(), self.match_case_insensitive)?;
+        *self.config.write().expect("Config lock poisoned") = Arc::new(config);
+        Ok(())
+    }
+
+    /// The mediaplayers to display in order of priority, where the options take precedence over the config file,
+    /// followed by those given by their identity
+    pub fn mediaplayers(&self) -> Vec<Pattern> {
+        let mut mediaplayers = match self.mediaplayers.is_empty() {
+            true => self.config().mediaplayers.clone().unwrap_or_default(),
+            false => self.mediaplayers.clone(),
+        };
+        mediaplayers.extend(self.identities.iter().cloned());
+        mediaplayers
+    }
+
+    /// The mediaplayers to ignore, where the options take precedence over the config file
+    pub fn ignore(&self) -> Vec<Pattern> {
+        match self.ignore.is_empty() {
+            true => self.config().ignore.clone().unwrap_or_default(),
+            false => self.ignore.clone(),
+        }
+    }
+
+    /// The format of output, where the options take precedence over the config file
+    pub fn format(&self) -> Template {
+        self.format
+            .clone()
+            .or_else(|| self.config().format.clone())
+            .unwrap_or_else(|| DEFAULT_FORMAT.clone())
+    }
+}
+
+/// Check that a signal for Waybar is in the range of real-time signals, between SIGRTMIN+1 and SIGRTMAX
+fn parse_signal(number: &str) -> Result<u8, String> {
+    match number.parse() {
+        Ok(number @ 1..=30) => Ok(number),
+        _ => Err(format!("invalid signal '{}', expected 1 to 30", number)),
+    }
+}
+
+/// Check that a number of seconds or the like is more than zero, since nothing can happen every zero seconds
+fn parse_positive<T>(number: &str) -> Result<T, String>
+where
+    T: std::str::FromStr + Default + PartialEq,
+{
+    match number.parse() {
+        Ok(number) if number != T::default() => Ok(number),
+        _ => Err(format!("invalid number '{}', expected more than 0", number)),
+    }
+}
+
+/// Split a comma separated list of patterns, skipping any blank entries
+fn split_list(list: &str, case_insensitive: bool) -> Result<Vec<Pattern>, pico_args::Error> {
+    // A regular expression can have commas of its own, such as in {1,3}, so it takes the rest of the list
+    let mut names = Vec::new();
+    let mut rest = list;
+    loop {
+        if rest.trim_start().starts_with("re:") {
+            names.push(rest);
+            break;
+        }
+        match rest.split_once(',') {
+            Some((name, next)) => {
+                names.push(name);
+                rest = next;
+            }
+            None => {
+                names.push(rest);
+                break;
+            }
+        }
+    }
+
+    names
+        .into_iter()
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(|name| {
+            Pattern::new(name, case_insensitive).map_err(|err| {
+                pico_args::Error::ArgumentParsingFailed {
+                    cause: format!("invalid pattern '{}': {}", name, err),
+                }
+            })
+        })
+        .collect()
+}
+
+/// Parse a template given by the user, telling which option it was if something is wrong with it
+fn parse_template(option: &str, template: &str) -> Result<Template, pico_args::Error> {
+    template
+        .parse()
+        .map_err(|err| pico_args::Error::ArgumentParsingFailed {
+            cause: format!("{}: {}", option, err),
+        })
+}
+
+/// Parse an optional template given by the user
+fn opt_template(
+    pargs: &mut pico_args::Arguments,
+    option: &'static str,
+) -> Result<Option<Template>, pico_args::Error> {
+    pargs
+        .opt_value_from_str::<_, String>(option)?
+        .map(|template| parse_template(option, &template))
+        .transpose()
 }
 
 /// Get the user arguments
@@ -28,21 +404,215 @@ pub fn parse_args() -> Result<Arguments, pico_args::Error> {
         std::process::exit(0);
     }
 
-    // Extract mediaplayer first to use it for glob determination
+    parse(pargs)
+}
+
+/// Parse options given as they would be on the command line, without the name of the program
+///
+/// Asking for help is an error, since showing [`HELP`] is up to the application rather than lizzy.
+pub fn parse_from(args: Vec<std::ffi::OsString>) -> Result<Arguments, pico_args::Error> {
+    let mut pargs = pico_args::Arguments::from_vec(args);
+
+    if pargs.contains(["-h", "--help"]) {
+        return Err(pico_args::Error::ArgumentParsingFailed {
+            cause: String::from("--help is left to the application, which can show options::HELP"),
+        });
+    }
+
+    parse(pargs)
+}
+
+fn parse(mut pargs: pico_args::Arguments) -> Result<Arguments, pico_args::Error> {
+    // A command is always the first argument, but its own arguments come after all options
+    let command_name = pargs.subcommand()?;
+
+    // Casing has to be known before any mediaplayer patterns are constructed
+    let case_insensitive = pargs.contains("--match-case-insensitive");
+
+    // The config file is read again whenever it changes, so its path is kept
+    let config_path: Option<PathBuf> = pargs.opt_value_from_str("--config")?;
+    let config = config::load(config_path.as_deref(), case_insensitive)
+        .map_err(|cause| pico_args::Error::ArgumentParsingFailed { cause })?;
+
+    // Mediaplayers are given as a comma separated list in order of priority
     let mediaplayer: String = pargs
         .opt_value_from_str("--mediaplayer")?
         .unwrap_or_else(String::new);
 
-    // Check for glob
-    let glob = mediaplayer.contains('*');
+    let mediaplayers: Vec<Pattern> = split_list(&mediaplayer, case_insensitive)?;
+
+    // Mediaplayers can be given by their identity as well, which comes after them in priority
+    let identities: Vec<Pattern> = match pargs.opt_value_from_str::<_, String>("--identity")? {
+        Some(identity) => split_list(&identity, case_insensitive)?
+            .into_iter()
+            .map(Pattern::for_identity)
+            .collect(),
+        None => Vec::new(),
+    };
+
+    // Mediaplayers to ignore can be given several times, as well as comma separated
+    let mut ignore: Vec<Pattern> = Vec::new();
+    for value in pargs.values_from_str::<_, String>("--ignore")? {
+        ignore.extend(split_list(&value, case_insensitive)?);
+    }
+
+    // Mediaplayers that should never pause ours, given the same way as those to ignore
+    let mut autotoggle_ignore: Vec<Pattern> = Vec::new();
+    for value in pargs.values_from_str::<_, String>("--autotoggle-ignore")? {
+        autotoggle_ignore.extend(split_list(&value, case_insensitive)?);
+    }
+
+    // Classes are given as a comma separated list of templates
+    let class: Vec<Template> = pargs
+        .opt_value_from_str::<_, String>("--class")?
+        .map(|class| {
+            class
+                .split(',')
+                .map(str::trim)
+                .filter(|class| !class.is_empty())
+                .map(|class| parse_template("--class", class))
+                .collect()
+        })
+        .transpose()?
+        .unwrap_or_default();
 
     let args = Arguments {
-        format: pargs
-            .opt_value_from_str("--format")?
-            .unwrap_or(String::from("{{artist}} - {{title}}")),
-        mediaplayer,
+        output: pargs
+            .opt_value_from_str("--output")?
+            .unwrap_or(OutputMode::Waybar),
+        backend: pargs
+            .opt_value_from_str("--backend")?
+            .unwrap_or(Backend::Mpris),
+        color: pargs.opt_value_from_str("--color")?,
+        escape: pargs
+            .opt_value_from_str("--escape")?
+            .unwrap_or(Escape::None),
+        format: opt_template(&mut pargs, "--format")?,
+        format_paused: opt_template(&mut pargs, "--format-paused")?,
+        format_stopped: opt_template(&mut pargs, "--format-stopped")?,
+        idle_text: pargs.opt_value_from_str("--idle-text")?,
+        length: pargs.opt_value_from_str("--length")?,
+        ellipsis: pargs
+            .opt_value_from_str("--ellipsis")?
+            .unwrap_or(String::from("…")),
+        scroll: pargs.contains("--scroll"),
+        scroll_width: pargs
+            .opt_value_from_fn("--scroll-width", parse_positive)?
+            .unwrap_or(30),
+        scroll_speed: pargs
+            .opt_value_from_fn("--scroll-speed", parse_positive)?
+            .unwrap_or(500),
+        tooltip_format: opt_template(&mut pargs, "--tooltip-format")?,
+        tooltip_metadata: pargs.contains("--tooltip-metadata"),
+        art: pargs.contains("--art"),
+        art_lookup: pargs.contains("--art-lookup"),
+        class,
+        mediaplayers,
+        identities,
+        ignore,
+        keep_mirrors: pargs.contains("--keep-mirrors"),
+        follow: pargs.contains("--follow"),
+        once: pargs.contains("--once"),
+        hide_paused_after: pargs.opt_value_from_str("--hide-paused-after")?,
+        launch_on_click: pargs.contains("--launch-on-click"),
         autotoggle: pargs.contains("--autotoggle"),
-        glob,
+        autotoggle_cooldown: pargs
+            .opt_value_from_str("--autotoggle-cooldown")?
+            .unwrap_or(1000),
+        autotoggle_ignore,
+        autotoggle_min_length: pargs.opt_value_from_str("--autotoggle-min-length")?,
+        autotoggle_audible: pargs.contains("--autotoggle-audible"),
+        pause_others: pargs.contains("--pause-others"),
+        all_players: pargs.contains("--all-players"),
+        discord_client_id: pargs.opt_value_from_str("--discord-client-id")?,
+        pause_on_lock: pargs.contains("--pause-on-lock"),
+        resume_on_unlock: pargs.contains("--resume-on-unlock"),
+        pause_on_suspend: pargs.contains("--pause-on-suspend"),
+        resume_on_wake: pargs.contains("--resume-on-wake"),
+        inhibit_idle: pargs.contains("--inhibit-idle"),
+        pause_on_microphone: pargs.contains("--pause-on-microphone"),
+        interval: pargs.opt_value_from_fn("--interval", parse_positive)?,
+        artist_separator: pargs
+            .opt_value_from_str("--artist-separator")?
+            .unwrap_or(String::from(", ")),
+        rating_style: pargs
+            .opt_value_from_str("--rating-style")?
+            .unwrap_or(RatingStyle::Stars),
+        playing_icon: pargs.opt_value_from_str("--playing-icon")?,
+        paused_icon: pargs.opt_value_from_str("--paused-icon")?,
+        playing: pargs.opt_value_from_str("--playing")?,
+        notplaying: pargs.opt_value_from_str("--notplaying")?,
+        shuffle_icon: pargs
+            .opt_value_from_str("--shuffle-icon")?
+            .unwrap_or(String::from("Shuffle")),
+        loop_track_icon: pargs
+            .opt_value_from_str("--loop-track-icon")?
+            .unwrap_or(String::from("Track")),
+        loop_playlist_icon: pargs
+            .opt_value_from_str("--loop-playlist-icon")?
+            .unwrap_or(String::from("Playlist")),
+        fifo: pargs.opt_value_from_str("--fifo")?,
+        output_file: pargs.opt_value_from_str("--output-file")?,
+        output_file_format: opt_template(&mut pargs, "--output-file-format")?,
+        signal: pargs.opt_value_from_fn("--signal", parse_signal)?,
+        on_change: pargs.opt_value_from_str("--on-change")?,
+        notify: pargs.contains("--notify"),
+        notify_format: parse_template(
+            "--notify-format",
+            &pargs
+                .opt_value_from_str::<_, String>("--notify-format")?
+                .unwrap_or(String::from("{{artist}}")),
+        )?,
+        dbus_service: pargs.contains("--dbus-service"),
+        serve: pargs.opt_value_from_str("--serve")?,
+        serve_origin: pargs.values_from_str("--serve-origin")?,
+        event_socket: pargs.opt_value_from_str("--event-socket")?,
+        control: pargs.contains("--control"),
+        socket: pargs.opt_value_from_str("--socket")?,
+        log_level: pargs
+            .opt_value_from_str("--log-level")?
+            .unwrap_or(tracing::Level::WARN),
+        log_file: pargs.opt_value_from_str("--log-file")?,
+        log_journald: pargs.contains("--log-journald"),
+        history: pargs.contains("--history"),
+        clean_titles: pargs.contains("--clean-titles"),
+        match_case_insensitive: case_insensitive,
+        config_path,
+        config: RwLock::new(Arc::new(config)),
+        command: None,
+    };
+
+    // Waybar and i3bar get JSON that has the text escaped already, so escaping it as JSON first would do it twice
+    if matches!(args.escape, Escape::Json)
+        && matches!(args.output, OutputMode::Waybar | OutputMode::I3bar)
+    {
+        return Err(pico_args::Error::ArgumentParsingFailed {
+            cause: String::from(
+                "--escape json is for plain output, since waybar and i3bar are JSON already",
+            ),
+        });
+    }
+
+    // Only the displayed track is scrobbled, and there is none of it when every mediaplayer is output
+    if args.all_players && args.config().listenbrainz.is_some() {
+        return Err(pico_args::Error::ArgumentParsingFailed {
+            cause: String::from("ListenBrainz can't scrobble with --all-players"),
+        });
+    }
+
+    // Likewise only the displayed track is recorded
+    if args.all_players && args.history {
+        return Err(pico_args::Error::ArgumentParsingFailed {
+            cause: String::from("--history can't record with --all-players"),
+        });
+    }
+
+    let args = Arguments {
+        command: match command_name {
+            Some(name) => Some(Command::parse(&name, &mut pargs)?),
+            None => None,
+        },
+        ..args
     };
 
     // It's up to the caller what to do with the remaining arguments.
@@ -0,0 +1,107 @@
+use crate::media::Media;
+use crate::track::Track;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// How long a track has to be played at most before it counts as listened to, no matter how long it is
+const MAX_LISTEN: Duration = Duration::from_secs(240);
+
+/// A track that is being played, along with how long it has been played and what is kept about it
+pub struct Play<T> {
+    track: Track,
+    // When the track started, in seconds since the epoch
+    pub started_at: u64,
+    played: Duration,
+    playing_since: Option<Instant>,
+    // The play counts once half of the track has played, or four minutes of longer tracks
+    needed: Duration,
+    pub about: T,
+}
+
+impl<T> Play<T> {
+    fn from_media(media: &Media, about: T) -> Self {
+        let length = media
+            .metadata
+            .length
+            .filter(|length| *length > 0)
+            .map(|length| Duration::from_micros(length as u64));
+
+        // A track that is picked up halfway started before lizzy noticed
+        let position = Duration::from_micros(media.position.unwrap_or_default().max(0) as u64);
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+
+        Play {
+            track: Track::from_media(media),
+            started_at: now.saturating_sub(position).as_secs(),
+            played: Duration::ZERO,
+            playing_since: None,
+            needed: length.map_or(MAX_LISTEN, |length| (length / 2).min(MAX_LISTEN)),
+            about,
+        }
+    }
+
+    /// How long the track has been played, counted up to the last update
+    pub fn played(&self) -> Duration {
+        self.played
+    }
+
+    /// Check if the track has been played long enough to count as listened to
+    pub fn listened(&self) -> bool {
+        self.played >= self.needed
+    }
+}
+
+/// The play of the track that is displayed, shared between updates of the output
+pub struct Plays<T> {
+    current: Option<Play<T>>,
+}
+
+impl<T> Default for Plays<T> {
+    fn default() -> Self {
+        Plays { current: None }
+    }
+}
+
+impl<T> Plays<T> {
+    /// Keep count of how long the track is played, starting over with what `about` keeps of a new track,
+    /// and return the play that ended if the track changed
+    pub fn update(
+        &mut self,
+        media: Option<&Media>,
+        about: impl FnOnce(&Media) -> Option<T>,
+    ) -> Option<Play<T>> {
+        let playing = media.is_some_and(|media| media.playbackstatus.as_deref() == Some("Playing"));
+
+        // Count the time played since the last update first, which may finish the play of the previous track
+        if let Some(play) = &mut self.current {
+            if let Some(since) = play.playing_since.take() {
+                play.played += since.elapsed();
+            }
+        }
+
+        // The trackid and length tell a new track apart from the same one, so that it is only counted once
+        let new_track = match (media, &self.current) {
+            (Some(media), Some(play)) => Track::from_media(media).is_new(&play.track),
+            (Some(_), None) => true,
+            (None, _) => false,
+        };
+        let ended = if new_track || media.is_none() {
+            let next = media.and_then(|media| Some(Play::from_media(media, about(media)?)));
+            std::mem::replace(&mut self.current, next)
+        } else {
+            None
+        };
+
+        if let (true, Some(play)) = (playing, &mut self.current) {
+            play.playing_since = Some(Instant::now());
+        }
+
+        ended
+    }
+
+    /// The play that is going on, if any
+    pub fn current(&mut self) -> Option<&mut Play<T>> {
+        self.current.as_mut()
+    }
+}
@@ -0,0 +1,123 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Instant;
+use zbus::fdo::PropertiesChanged;
+use zbus::Message;
+
+/// Where a mediaplayer was in the track at some point, to work out where it is now without asking it
+#[derive(Clone, Copy)]
+struct Clock {
+    position: i64,
+    rate: f64,
+    playing: bool,
+    since: Instant,
+}
+
+impl Clock {
+    /// The position by now, which moves on at the rate of playback while playing
+    fn now(&self) -> i64 {
+        if !self.playing {
+            return self.position;
+        }
+        let elapsed = self.since.elapsed().as_micros() as f64 * self.rate;
+        self.position.saturating_add(elapsed as i64).max(0)
+    }
+
+    /// Start over from where the mediaplayer is by now, so that a change only applies from here on
+    fn restart(&mut self) {
+        self.position = self.now();
+        self.since = Instant::now();
+    }
+}
+
+/// The clocks of mediaplayers by their unique names, kept only while nothing makes them uncertain
+#[derive(Default)]
+pub struct Clocks(Mutex<HashMap<String, Clock>>);
+
+impl Clocks {
+    /// The position of a mediaplayer by now, if it has a clock
+    pub fn now(&self, owner: &str) -> Option<i64> {
+        self.0
+            .lock()
+            .expect("Clocks lock poisoned")
+            .get(owner)
+            .map(Clock::now)
+    }
+
+    /// Start the clock of a mediaplayer from a position it was asked for
+    pub fn start(&self, owner: &str, position: i64, rate: f64, playing: bool) {
+        self.0.lock().expect("Clocks lock poisoned").insert(
+            owner.to_string(),
+            Clock {
+                position,
+                rate,
+                playing,
+                since: Instant::now(),
+            },
+        );
+    }
+
+    /// Move the clock of a mediaplayer to where it seeked to, returning whether it had one
+    pub fn seek(&self, owner: &str, position: i64) -> bool {
+        match self.0.lock().expect("Clocks lock poisoned").get_mut(owner) {
+            Some(clock) => {
+                clock.position = position;
+                clock.since = Instant::now();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Keep the clock of the sender in step with a change of its properties, whether it is displayed or not
+    pub fn observe(&self, message: &Message) {
+        let Some(sender) = message.header().sender().map(|sender| sender.to_string()) else {
+            return;
+        };
+        let Some(properties) = PropertiesChanged::from_message(message.clone()) else {
+            return;
+        };
+        let Ok(args) = properties.args() else {
+            return;
+        };
+        let changed = args.changed_properties();
+
+        let mut clocks = self.0.lock().expect("Clocks lock poisoned");
+
+        // Another track starts somewhere else, so the position is asked for again
+        if changed.contains_key("Metadata") {
+            clocks.remove(&sender);
+            return;
+        }
+
+        let Some(clock) = clocks.get_mut(&sender) else {
+            return;
+        };
+        clock.restart();
+
+        if let Some(rate) = changed
+            .get("Rate")
+            .and_then(|rate| rate.downcast_ref::<f64>().ok())
+        {
+            clock.rate = rate;
+        }
+        if let Some(status) = changed
+            .get("PlaybackStatus")
+            .and_then(|status| status.downcast_ref::<String>().ok())
+        {
+            clock.playing = status == "Playing";
+        }
+        // Position is not supposed to be sent, but it is the best there is if some mediaplayer does
+        if let Some(position) = changed
+            .get("Position")
+            .and_then(|position| position.downcast_ref::<i64>().ok())
+        {
+            clock.position = position;
+        }
+    }
+
+    /// Forget all clocks, since a new session bus hands out the same unique names again
+    pub fn forget(&self) {
+        self.0.lock().expect("Clocks lock poisoned").clear();
+    }
+}
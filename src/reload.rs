@@ -0,0 +1,53 @@
+use crate::options::Arguments;
+use crate::BoxedError;
+use std::path::Path;
+use std::process::Stdio;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::process::Command;
+use tokio::sync::Notify;
+use tracing::{error, info};
+
+/// Watch the config file with inotifywait, reading it again whenever it changes and telling the property stream
+pub async fn watch(options: &Arguments, path: &Path, reloaded: &Notify) -> Result<(), BoxedError> {
+    // Editors tend to replace the file rather than write to it, which only the directory sees
+    let (Some(directory), Some(name)) = (path.parent(), path.file_name()) else {
+        return Err(format!("{} is not a file", path.display()).into());
+    };
+
+    let mut watcher = Command::new("inotifywait")
+        .args(["--monitor", "--quiet", "--format", "%f"])
+        .args(["--event", "close_write,moved_to,delete"])
+        .arg(directory)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|err| {
+            format!(
+                "could not run inotifywait, which is part of inotify-tools: {}",
+                err
+            )
+        })?;
+    let stdout = watcher
+        .stdout
+        .take()
+        .ok_or("Failed to read the events of inotifywait")?;
+    let mut events = BufReader::new(stdout).lines();
+
+    while let Some(changed) = events.next_line().await? {
+        if name != changed.as_str() {
+            continue;
+        }
+
+        match options.reload_config() {
+            Ok(()) => {
+                info!("Reloaded the config file {}", path.display());
+                reloaded.notify_one();
+            }
+            Err(err) => error!("Keeping the config file as it was: {}", err),
+        }
+    }
+
+    Err("inotifywait stopped sending events".into())
+}
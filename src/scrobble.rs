@@ -0,0 +1,142 @@
+use crate::config::ListenBrainz;
+use crate::media::Media;
+use crate::play::Plays;
+use crate::BoxedError;
+use serde_json::{json, Value};
+use std::process::Stdio;
+use std::sync::Mutex;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tracing::{debug, error};
+
+/// What ListenBrainz is told about a track that is being listened to
+struct Listen {
+    metadata: Value,
+    playing_now: bool,
+    submitted: bool,
+}
+
+impl Listen {
+    /// Start listening to the media, unless it lacks the artist or title that ListenBrainz needs
+    fn from_media(media: &Media) -> Option<Self> {
+        let artist = media.metadata.artist.as_ref()?;
+        let title = media.metadata.title.as_ref()?;
+
+        let mut additional_info = json!({
+            "media_player": media.player,
+            "submission_client": "lizzy",
+            "submission_client_version": env!("CARGO_PKG_VERSION"),
+        });
+        if let Some(length) = media.metadata.length.filter(|length| *length > 0) {
+            additional_info["duration_ms"] = (length / 1000).into();
+        }
+
+        let mut metadata = json!({
+            "artist_name": artist,
+            "track_name": title,
+            "additional_info": additional_info,
+        });
+        if let Some(album) = &media.metadata.album {
+            metadata["release_name"] = album.as_str().into();
+        }
+
+        Some(Listen {
+            metadata,
+            playing_now: false,
+            submitted: false,
+        })
+    }
+}
+
+/// The listen of the track that is displayed, shared between updates of the output
+#[derive(Default)]
+pub struct Scrobbler {
+    plays: Plays<Listen>,
+}
+
+/// Keep count of how long the track is played, and submit it to ListenBrainz once it has been listened to
+pub fn update(scrobbler: &Mutex<Scrobbler>, listenbrainz: &ListenBrainz, media: Option<&Media>) {
+    let mut scrobbler = scrobbler.lock().expect("Scrobbler lock poisoned");
+    let playing = media.is_some_and(|media| media.playbackstatus.as_deref() == Some("Playing"));
+
+    // The listen of the previous track may have been finished just before it ended
+    let mut ended = scrobbler.plays.update(media, Listen::from_media);
+    for play in ended.iter_mut().chain(scrobbler.plays.current()) {
+        if play.listened() && !play.about.submitted {
+            play.about.submitted = true;
+            submit(
+                listenbrainz,
+                "single",
+                json!({
+                    "listened_at": play.started_at,
+                    "track_metadata": play.about.metadata,
+                }),
+            );
+        }
+    }
+
+    if let (true, Some(play)) = (playing, scrobbler.plays.current()) {
+        if !play.about.playing_now {
+            play.about.playing_now = true;
+            submit(
+                listenbrainz,
+                "playing_now",
+                json!({ "track_metadata": play.about.metadata }),
+            );
+        }
+    }
+}
+
+/// Submit a listen in the background, so that a slow ListenBrainz doesn't hold up the output
+fn submit(listenbrainz: &ListenBrainz, listen_type: &'static str, payload: Value) {
+    let listenbrainz = listenbrainz.clone();
+    let body = json!({ "listen_type": listen_type, "payload": [payload] }).to_string();
+
+    tokio::spawn(async move {
+        match post(&listenbrainz, &body).await {
+            Ok(()) => debug!(
+                event = "scrobble",
+                "Submitted {} to ListenBrainz", listen_type
+            ),
+            Err(err) => error!(
+                event = "scrobble",
+                "Failed to submit {} to ListenBrainz: {}", listen_type, err
+            ),
+        }
+    });
+}
+
+/// Post the listens with curl, passing the token on its standard input to keep it out of the process list
+async fn post(listenbrainz: &ListenBrainz, body: &str) -> Result<(), BoxedError> {
+    let mut curl = Command::new("curl")
+        .args(["--silent", "--show-error", "--fail", "--max-time", "10"])
+        .args(["--config", "-"])
+        .args(["--header", "Content-Type: application/json"])
+        .args(["--data-binary", body])
+        .arg(format!(
+            "{}/1/submit-listens",
+            listenbrainz.url.trim_end_matches('/')
+        ))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    if let Some(mut stdin) = curl.stdin.take() {
+        stdin
+            .write_all(
+                format!("header = \"Authorization: Token {}\"\n", listenbrainz.token).as_bytes(),
+            )
+            .await?;
+    }
+
+    let output = curl.wait_with_output().await?;
+    if !output.status.success() {
+        return Err(String::from_utf8_lossy(&output.stderr)
+            .trim()
+            .to_string()
+            .into());
+    }
+
+    Ok(())
+}
@@ -0,0 +1,360 @@
+use crate::hash::{base64, sha1};
+use crate::media;
+use crate::{accept_failed, BoxedError};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{
+    AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader,
+};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, watch};
+use tracing::debug;
+
+/// The path that serves what is playing, both as JSON and as a WebSocket
+const PATH: &str = "/now-playing";
+
+/// Appended to the key of the client to prove that the server speaks WebSocket
+const WEBSOCKET_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// The opcodes of WebSocket frames
+const TEXT: u8 = 0x1;
+const CLOSE: u8 = 0x8;
+const PING: u8 = 0x9;
+const PONG: u8 = 0xa;
+
+/// A request is only a few lines, so anything longer is refused
+const MAX_HEADERS: usize = 100;
+
+/// The longest line of a request, which is plenty for anything a client has to say
+const MAX_LINE: u64 = 8192;
+
+/// How long a client has to send its request, so that one that never does isn't kept around
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The request of a client, with the header names in lowercase since they are case insensitive
+struct Request {
+    method: String,
+    path: String,
+    headers: HashMap<String, String>,
+}
+
+/// Accept clients and serve them what is playing, each on its own
+///
+/// Web pages of other origins are only let in if their origin is one of the origins, or if those include `*`.
+pub async fn serve(
+    listener: TcpListener,
+    now_playing: watch::Receiver<Option<String>>,
+    origins: Vec<String>,
+) -> Result<(), BoxedError> {
+    let origins: Arc<[String]> = origins.into();
+
+    loop {
+        // A client that fails to connect, or too many at once, is no reason to stop serving the others
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(err) => {
+                accept_failed("server", err).await;
+                continue;
+            }
+        };
+        let now_playing = now_playing.clone();
+        let origins = origins.clone();
+
+        tokio::spawn(async move {
+            if let Err(err) = handle_client(stream, now_playing, &origins).await {
+                debug!("HTTP client failed: {}", err);
+            }
+        });
+    }
+}
+
+/// Read a line of the request, refusing one that is too long
+async fn read_line(stream: &mut (impl AsyncBufRead + Unpin)) -> Result<String, BoxedError> {
+    let mut line = String::new();
+    stream.take(MAX_LINE).read_line(&mut line).await?;
+    if line.len() as u64 >= MAX_LINE && !line.ends_with('\n') {
+        return Err("Request line is too long".into());
+    }
+    Ok(line)
+}
+
+/// Read the request line and the headers that follow it
+async fn read_request(stream: &mut (impl AsyncBufRead + Unpin)) -> Result<Request, BoxedError> {
+    let request_line = read_line(stream).await?;
+    let mut words = request_line.split_whitespace();
+    let (method, target) = (
+        words.next().unwrap_or_default(),
+        words.next().unwrap_or_default(),
+    );
+    let path = target.split('?').next().unwrap_or_default();
+
+    let mut headers = HashMap::new();
+    for _ in 0..MAX_HEADERS {
+        let line = read_line(stream).await?;
+        if line.trim_end().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            headers.insert(name.trim().to_lowercase(), value.trim().to_string());
+        }
+    }
+
+    Ok(Request {
+        method: method.to_string(),
+        path: path.to_string(),
+        headers,
+    })
+}
+
+/// Check if a request may be answered, which goes for any client but a web page of an origin that is not let in
+fn is_allowed(origins: &[String], origin: Option<&str>) -> bool {
+    match origin {
+        // Only browsers send an origin, so anything else such as curl or a bar is let in
+        None => true,
+        Some(origin) => origins
+            .iter()
+            .any(|allowed| allowed == "*" || allowed == origin),
+    }
+}
+
+/// Read the request and answer it with JSON, or upgrade it to a WebSocket
+async fn handle_client(
+    stream: TcpStream,
+    mut now_playing: watch::Receiver<Option<String>>,
+    origins: &[String],
+) -> Result<(), BoxedError> {
+    let mut stream = BufReader::new(stream);
+
+    let Request {
+        method,
+        path,
+        headers,
+    } = tokio::time::timeout(REQUEST_TIMEOUT, read_request(&mut stream))
+        .await
+        .map_err(|_| "Timed out reading the request")??;
+
+    // Any web page the user visits could otherwise find out what they are playing
+    let origin = headers.get("origin").map(String::as_str);
+    if !is_allowed(origins, origin) {
+        return respond(&mut stream, "403 Forbidden", None, None).await;
+    }
+
+    let upgrade = headers
+        .get("upgrade")
+        .is_some_and(|upgrade| upgrade.eq_ignore_ascii_case("websocket"));
+
+    match (
+        method.as_str(),
+        path.as_str(),
+        headers.get("sec-websocket-key"),
+    ) {
+        ("GET", PATH, Some(key)) if upgrade => {
+            stream
+                .write_all(
+                    format!(
+                        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+                        accept(key)
+                    )
+                    .as_bytes(),
+                )
+                .await?;
+            stream_updates(stream, now_playing).await
+        }
+        ("GET", PATH, _) => {
+            respond(
+                &mut stream,
+                "200 OK",
+                Some(&media::latest(&mut now_playing)),
+                origin,
+            )
+            .await
+        }
+        (_, PATH, _) => respond(&mut stream, "405 Method Not Allowed", None, origin).await,
+        _ => respond(&mut stream, "404 Not Found", None, origin).await,
+    }
+}
+
+/// Answer with a JSON body, or none for an error, and close the connection,
+/// letting the web page of an origin that is let in read the answer
+async fn respond(
+    stream: &mut BufReader<TcpStream>,
+    status: &str,
+    body: Option<&str>,
+    origin: Option<&str>,
+) -> Result<(), BoxedError> {
+    let content_type = match body {
+        Some(_) => "Content-Type: application/json\r\n",
+        None => "",
+    };
+    let allow_origin = match origin {
+        Some(origin) => format!(
+            "Access-Control-Allow-Origin: {}\r\nVary: Origin\r\n",
+            origin
+        ),
+        None => String::new(),
+    };
+    let body = body.unwrap_or_default();
+
+    stream
+        .write_all(
+            format!(
+                "HTTP/1.1 {}\r\n{}Content-Length: {}\r\n{}Connection: close\r\n\r\n{}",
+                status,
+                content_type,
+                body.len(),
+                allow_origin,
+                body
+            )
+            .as_bytes(),
+        )
+        .await?;
+    Ok(())
+}
+
+/// Send what is playing on the WebSocket, and again whenever it changes, until the client goes away
+async fn stream_updates(
+    stream: BufReader<TcpStream>,
+    mut now_playing: watch::Receiver<Option<String>>,
+) -> Result<(), BoxedError> {
+    let (mut reader, mut writer) = tokio::io::split(stream);
+
+    // Frames are read on their own, since reading one is not something to stop halfway for an update
+    let (sender, mut frames) = mpsc::channel(1);
+    let reading = tokio::spawn(async move {
+        loop {
+            let frame = read_frame(&mut reader).await;
+            let failed = frame.is_err();
+            if sender.send(frame).await.is_err() || failed {
+                return;
+            }
+        }
+    });
+
+    let result = async {
+        send_frame(&mut writer, TEXT, media::latest(&mut now_playing).as_bytes()).await?;
+
+        loop {
+            tokio::select! {
+                changed = now_playing.changed() => {
+                    if changed.is_err() {
+                        return send_frame(&mut writer, CLOSE, &[]).await;
+                    }
+                    send_frame(&mut writer, TEXT, media::latest(&mut now_playing).as_bytes()).await?;
+                }
+                frame = frames.recv() => {
+                    let (opcode, payload) = frame.ok_or("WebSocket reader stopped")??;
+                    match opcode {
+                        CLOSE => return send_frame(&mut writer, CLOSE, &[]).await,
+                        PING => send_frame(&mut writer, PONG, &payload).await?,
+                        // Clients have nothing to say, so anything else is ignored
+                        _ => {}
+                    }
+                }
+            }
+        }
+    }
+    .await;
+
+    reading.abort();
+    result
+}
+
+/// Send a frame, which the server never masks
+async fn send_frame(
+    writer: &mut (impl AsyncWrite + Unpin),
+    opcode: u8,
+    payload: &[u8],
+) -> Result<(), BoxedError> {
+    let mut frame = vec![0x80 | opcode];
+    match payload.len() {
+        length @ 0..=125 => frame.push(length as u8),
+        length @ 126..=0xffff => {
+            frame.push(126);
+            frame.extend_from_slice(&(length as u16).to_be_bytes());
+        }
+        length => {
+            frame.push(127);
+            frame.extend_from_slice(&(length as u64).to_be_bytes());
+        }
+    }
+    frame.extend_from_slice(payload);
+
+    writer.write_all(&frame).await?;
+    Ok(())
+}
+
+/// Read a frame from the client, which always masks it
+async fn read_frame(reader: &mut (impl AsyncRead + Unpin)) -> Result<(u8, Vec<u8>), BoxedError> {
+    let mut header = [0; 2];
+    reader.read_exact(&mut header).await?;
+    let opcode = header[0] & 0x0f;
+
+    let length = match header[1] & 0x7f {
+        126 => reader.read_u16().await? as u64,
+        127 => reader.read_u64().await?,
+        length => length as u64,
+    };
+    // Frames of clients are small, so a large one is not worth reading
+    if length > 0xffff {
+        return Err("WebSocket frame is too large".into());
+    }
+
+    let mut mask = [0; 4];
+    if header[1] & 0x80 != 0 {
+        reader.read_exact(&mut mask).await?;
+    }
+
+    let mut payload = vec![0; length as usize];
+    reader.read_exact(&mut payload).await?;
+    for (i, byte) in payload.iter_mut().enumerate() {
+        *byte ^= mask[i % 4];
+    }
+
+    Ok((opcode, payload))
+}
+
+/// The answer to the key of a WebSocket handshake, proving that the server speaks WebSocket
+fn accept(key: &str) -> String {
+    base64(&sha1(format!("{}{}", key, WEBSOCKET_GUID).as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_the_handshake_of_rfc_6455() {
+        assert_eq!(
+            accept("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+
+    #[test]
+    fn lets_in_only_the_given_origins() {
+        let origins = vec![String::from("http://localhost:3000")];
+        assert!(is_allowed(&[], None));
+        assert!(!is_allowed(&[], Some("https://example.com")));
+        assert!(is_allowed(&origins, Some("http://localhost:3000")));
+        assert!(!is_allowed(&origins, Some("https://example.com")));
+        assert!(is_allowed(
+            &[String::from("*")],
+            Some("https://example.com")
+        ));
+    }
+
+    #[tokio::test]
+    async fn refuses_lines_that_are_too_long() {
+        let mut request = BufReader::new(&b"GET /now-playing HTTP/1.1\r\n\r\n"[..]);
+        assert_eq!(
+            read_line(&mut request)
+                .await
+                .expect("failed to read a short line"),
+            "GET /now-playing HTTP/1.1\r\n"
+        );
+
+        let long = vec![b'a'; MAX_LINE as usize + 1];
+        assert!(read_line(&mut BufReader::new(&long[..])).await.is_err());
+    }
+}
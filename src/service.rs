@@ -0,0 +1,90 @@
+use crate::media::Media;
+use crate::BoxedError;
+use zbus::object_server::SignalEmitter;
+use zbus::{interface, Connection};
+
+const PATH: &str = "/se/stefur/lizzy";
+
+/// The state lizzy currently displays, published on the session bus for other tools to consume
+#[derive(Default)]
+pub struct Service {
+    artist: String,
+    title: String,
+    status: String,
+    player: String,
+}
+
+#[interface(name = "se.stefur.lizzy")]
+impl Service {
+    #[zbus(property)]
+    fn artist(&self) -> String {
+        self.artist.clone()
+    }
+
+    #[zbus(property)]
+    fn title(&self) -> String {
+        self.title.clone()
+    }
+
+    #[zbus(property)]
+    fn status(&self) -> String {
+        self.status.clone()
+    }
+
+    #[zbus(property)]
+    fn player(&self) -> String {
+        self.player.clone()
+    }
+
+    /// Emitted whenever the displayed media changes, with empty strings when the output is cleared
+    #[zbus(signal)]
+    async fn changed(
+        emitter: &SignalEmitter<'_>,
+        artist: &str,
+        title: &str,
+        status: &str,
+        player: &str,
+    ) -> zbus::Result<()>;
+}
+
+/// Register the service on the session bus
+pub async fn register(connection: &Connection) -> Result<(), BoxedError> {
+    connection
+        .object_server()
+        .at(PATH, Service::default())
+        .await?;
+    connection.request_name("se.stefur.lizzy").await?;
+    Ok(())
+}
+
+/// Publish the displayed media, or that nothing is displayed
+pub async fn publish(connection: &Connection, media: Option<&Media>) -> Result<(), BoxedError> {
+    let interface = connection
+        .object_server()
+        .interface::<_, Service>(PATH)
+        .await?;
+    let mut service = interface.get_mut().await;
+
+    let field = |value: Option<&Option<String>>| value.cloned().flatten().unwrap_or_default();
+
+    service.artist = field(media.map(|media| &media.metadata.artist));
+    service.title = field(media.map(|media| &media.metadata.title));
+    service.status = field(media.map(|media| &media.playbackstatus));
+    service.player = field(media.map(|media| &media.player));
+
+    let emitter = interface.signal_emitter();
+    service.artist_changed(emitter).await?;
+    service.title_changed(emitter).await?;
+    service.status_changed(emitter).await?;
+    service.player_changed(emitter).await?;
+    Service::changed(
+        emitter,
+        &service.artist,
+        &service.title,
+        &service.status,
+        &service.player,
+    )
+    .await?;
+
+    Ok(())
+}
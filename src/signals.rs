@@ -0,0 +1,27 @@
+use crate::control::{ControlMessage, Request};
+use crate::BoxedError;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::{mpsc, oneshot};
+use tracing::debug;
+
+/// Turn SIGUSR1 and SIGUSR2 into requests to the property stream, the same as `lizzy control refresh` and `lizzy control play-pause`
+pub async fn forward(sender: mpsc::Sender<ControlMessage>) -> Result<(), BoxedError> {
+    let mut reprint = signal(SignalKind::user_defined1())?;
+    let mut toggle = signal(SignalKind::user_defined2())?;
+
+    loop {
+        let request = tokio::select! {
+            _ = reprint.recv() => Request::Refresh,
+            _ = toggle.recv() => Request::Call("PlayPause"),
+        };
+
+        let (reply_sender, reply) = oneshot::channel();
+        if sender.send((request, reply_sender)).await.is_err() {
+            return Ok(());
+        }
+        // There is nobody to tell, and nothing being displayed is not worth more than a note
+        if let Ok(Err(err)) = reply.await {
+            debug!("Failed to handle the signal: {}", err);
+        }
+    }
+}
@@ -0,0 +1,795 @@
+use crate::autotoggle::{
+    autotoggle_ignored, forget_autopause, pause_if_playing, pause_others, resume, toggle_against,
+};
+use crate::control::{self, ControlMessage};
+use crate::media::Media;
+use crate::options::{Arguments, OutputMode};
+use crate::BoxedError;
+use crate::{
+    audio, follow_sender, forget_names, get_first_match, get_property, handle_request, hide_paused,
+    is_ignored, is_mirror, logind, matches_any, move_on, name_owner, output, output_all,
+    output_current, parse_msg_args, refresh, resolve_mediaplayer, sender_matches, systemd, tick,
+    toggle_playback, unfollow, State,
+};
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tracing::{debug, error, info, warn};
+use unicode_width::UnicodeWidthStr;
+use zbus::export::futures_util::stream::StreamExt;
+use zbus::fdo::DBusProxy;
+use zbus::fdo::PropertiesChanged;
+use zbus::names::BusName;
+use zbus::zvariant::NoneValue;
+use zbus::Connection;
+use zbus::MatchRule;
+use zbus::Message;
+use zbus::MessageStream;
+
+/// Pause the mediaplayer that is playing, remembering it so that it can be resumed later on
+async fn pause_playing(
+    connection: &Connection,
+    options: &Arguments,
+    state: &State,
+    paused: &Mutex<Option<String>>,
+    reason: &str,
+) -> Result<(), BoxedError> {
+    let Some(mediaplayer_busname) = resolve_mediaplayer(connection, options, state).await? else {
+        return Ok(());
+    };
+    let playbackstatus: String = get_property(connection, &mediaplayer_busname, "PlaybackStatus")
+        .await?
+        .downcast()?;
+
+    // Only what was playing is resumed later on
+    if playbackstatus == "Playing" {
+        info!(player = %mediaplayer_busname, event = "pause", "Pausing {} since the {}", mediaplayer_busname, reason);
+        toggle_playback(connection, &mediaplayer_busname, "Pause").await?;
+        *paused.lock().expect("Paused lock poisoned") = Some(mediaplayer_busname);
+    }
+
+    Ok(())
+}
+
+/// Resume the mediaplayer that was paused, if the user wants that
+async fn resume_paused(
+    connection: &Connection,
+    paused: &Mutex<Option<String>>,
+    resume: bool,
+    reason: &str,
+) -> Result<(), BoxedError> {
+    let paused = paused.lock().expect("Paused lock poisoned").take();
+    if let Some(mediaplayer_busname) = paused.filter(|_| resume) {
+        info!(player = %mediaplayer_busname, event = "resume", "Resuming {} since the {}", mediaplayer_busname, reason);
+        toggle_playback(connection, &mediaplayer_busname, "Play").await?;
+    }
+
+    Ok(())
+}
+
+/// Pause playback when the session locks or the system suspends, and optionally resume it afterwards
+async fn handle_session_event(
+    connection: &Connection,
+    options: &Arguments,
+    state: &State,
+    event: logind::Event,
+) -> Result<(), BoxedError> {
+    match event {
+        logind::Event::Lock => {
+            pause_playing(
+                connection,
+                options,
+                state,
+                &state.lock_paused,
+                "session locked",
+            )
+            .await
+        }
+        logind::Event::Unlock => {
+            resume_paused(
+                connection,
+                &state.lock_paused,
+                options.resume_on_unlock,
+                "session unlocked",
+            )
+            .await
+        }
+        logind::Event::Suspend(inhibitor) => {
+            let result = pause_playing(
+                connection,
+                options,
+                state,
+                &state.suspend_paused,
+                "system suspends",
+            )
+            .await;
+            // Suspending goes on once the lock is dropped, which has to happen even if pausing failed
+            drop(inhibitor);
+            result
+        }
+        logind::Event::Wake => {
+            resume_paused(
+                connection,
+                &state.suspend_paused,
+                options.resume_on_wake,
+                "system woke up",
+            )
+            .await
+        }
+    }
+}
+
+/// How long to gather messages from the same sender before handling them
+const BURST_WINDOW: Duration = Duration::from_millis(50);
+
+/// How often to look whether the percentage of the track played has moved on
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Check if a message of changed properties contains a change of playbackstatus
+fn changes_state(msg: &Message) -> bool {
+    PropertiesChanged::from_message(msg.clone()).is_some_and(|properties| {
+        properties
+            .args()
+            .is_ok_and(|args| args.changed_properties().contains_key("PlaybackStatus"))
+    })
+}
+
+/// Start a message stream to listen for property changes
+pub async fn property_changes_stream(
+    connection: Connection,
+    options: &Arguments,
+    state: Arc<State>,
+    control_messages: &mut Option<mpsc::Receiver<ControlMessage>>,
+    session_events: &mut Option<mpsc::Receiver<logind::Event>>,
+    microphone_changes: &mut Option<mpsc::Receiver<bool>>,
+) -> Result<(), BoxedError> {
+    // Define a rule to catch properties changed
+    let rule: MatchRule = MatchRule::builder()
+        .msg_type(zbus::message::Type::Signal)
+        .interface("org.freedesktop.DBus.Properties")?
+        .member("PropertiesChanged")?
+        .path("/org/mpris/MediaPlayer2")?
+        .build();
+
+    // Seeking is signalled separately, since the position is not a property that changes
+    let seeked_rule: MatchRule = MatchRule::builder()
+        .msg_type(zbus::message::Type::Signal)
+        .interface("org.mpris.MediaPlayer2.Player")?
+        .member("Seeked")?
+        .path("/org/mpris/MediaPlayer2")?
+        .build();
+    let mut seeked_stream =
+        MessageStream::for_match_rule(seeked_rule, &connection, Some(10)).await?;
+
+    // A proxy to get name owners
+    let dbus_proxy = DBusProxy::new(&connection).await?;
+
+    // The mediaplayer bus name, resolved from the mediaplayers defined by the user, but will be null until then or if left undefined
+    let mut mediaplayer_busname: String = BusName::null_value().to_owned();
+
+    // A mediaplayer switched to via the control socket, which takes precedence until it closes
+    let mut pinned_busname: Option<String> = None;
+
+    let mut property_stream = MessageStream::for_match_rule(
+        rule,
+        &connection,
+        // No big queue needed here
+        Some(10),
+    )
+    .await?;
+
+    // Optionally re-render the output on an interval to keep the position current
+    let mut ticker = options
+        .interval
+        .map(|seconds| tokio::time::interval(Duration::from_secs(seconds)));
+
+    // Waybar gets the percentage of the track played, which moves on by itself while playing even without an interval
+    let mut progress_ticker = (options.interval.is_none()
+        && matches!(options.output, OutputMode::Waybar))
+    .then(|| tokio::time::interval(PROGRESS_INTERVAL));
+
+    // Optionally scroll long output through a window of fixed width
+    let mut scroll_ticker = options
+        .scroll
+        .then(|| tokio::time::interval(Duration::from_millis(options.scroll_speed)));
+
+    // Ping the watchdog of systemd from this loop, so that it notices if the loop ever hangs
+    let mut watchdog_ticker = systemd::watchdog_interval().map(tokio::time::interval);
+
+    // A message from another sender that arrived while gathering a burst of messages
+    let mut pending: Option<Message> = None;
+
+    // Everything is in place, so a unit with Type=notify can consider lizzy started
+    if let Err(err) = systemd::notify("READY=1") {
+        warn!("Failed to notify systemd: {}", err);
+    }
+
+    // Start catching messages on the stream
+    loop {
+        let msg = if let Some(msg) = pending.take() {
+            Some(Ok(msg))
+        } else {
+            tokio::select! {
+                msg = property_stream.next() => msg,
+                _ = hide_paused(options, &state) => {
+                    // Going through the output once more hides the paused media everywhere it is shown
+                    let displayed = state.displayed.lock().expect("Displayed lock poisoned").clone();
+                    output(&connection, options, &state, displayed.as_ref()).await;
+                    continue;
+                }
+                _ = tick(&mut watchdog_ticker) => {
+                    if let Err(err) = systemd::notify("WATCHDOG=1") {
+                        warn!("Failed to ping the systemd watchdog: {}", err);
+                    }
+                    continue;
+                }
+                _ = tick(&mut scroll_ticker) => {
+                    let displayed = state.displayed.lock().expect("Displayed lock poisoned").clone();
+
+                    // Only scroll while playing, and only text that doesn't fit anyway
+                    if let Some(media) = displayed {
+                        let playing = media.playbackstatus.as_deref() == Some("Playing");
+                        if playing && media.render(&media.format(options), options).is_some_and(|text| text.width() > options.scroll_width) {
+                            media.send(options, &state.sink, state.scroll_offset.fetch_add(1, Ordering::Relaxed) + 1);
+                        }
+                    }
+                    continue;
+                }
+                _ = tick(&mut progress_ticker) => {
+                    let displayed = state.displayed.lock().expect("Displayed lock poisoned").clone();
+
+                    // The output is only sent again once the percentage has moved on, since the same output is left out
+                    if let Some(player) = displayed.filter(Media::progressing).and_then(|media| media.player) {
+                        move_on(&connection, options, &state, &format!("org.mpris.MediaPlayer2.{}", player)).await;
+                    }
+                    continue;
+                }
+                _ = tick(&mut ticker) => {
+                    // Make sure we ask the pinned mediaplayer, the most recently active one or the running one with the highest priority
+                    if let Some(pinned) = &pinned_busname {
+                        pinned.clone_into(&mut mediaplayer_busname);
+                    } else if options.follow {
+                        mediaplayer_busname = state
+                            .active_players
+                            .lock()
+                            .expect("Active players lock poisoned")
+                            .last()
+                            .cloned()
+                            .unwrap_or_default();
+                    } else if !options.mediaplayers().is_empty() {
+                        mediaplayer_busname = match get_first_match(&dbus_proxy, &state, options).await {
+                            Ok(Some(matching_busname)) => matching_busname.to_string(),
+                            _ => BusName::null_value().to_owned(),
+                        };
+                    }
+
+                    // Nothing to refresh until we know which mediaplayer to ask, and nothing to ask while its position can be worked out
+                    if !mediaplayer_busname.is_empty() && !move_on(&connection, options, &state, &mediaplayer_busname).await {
+                        if let Err(err) = refresh(&connection, options, &state, &mediaplayer_busname).await {
+                            error!("Failed to refresh the output: {}", err);
+                        }
+                    }
+                    continue;
+                }
+                Some(Ok(seeked)) = seeked_stream.next() => {
+                    let sender = seeked.header().sender().map(|sender| sender.to_string());
+                    if let (Some(sender), Ok(position)) = (sender, seeked.body().deserialize::<i64>()) {
+                        debug!(sender = %sender, event = "received", "Received a seek to {} from {}", position, sender);
+                        if state.clocks.seek(&sender, position) && !options.all_players {
+                            move_on(&connection, options, &state, &sender).await;
+                        }
+                    }
+                    continue;
+                }
+                message = control::next_message(control_messages) => {
+                    match message {
+                        Some((request, reply)) => {
+                            let result = handle_request(
+                                &connection,
+                                &dbus_proxy,
+                                options,
+                                &state,
+                                request,
+                                &mut mediaplayer_busname,
+                                &mut pinned_busname,
+                            )
+                            .await;
+                            // The client may have given up waiting, which is fine
+                            let _ = reply.send(result);
+                        }
+                        // The control socket is gone, so stop waiting for it
+                        None => *control_messages = None,
+                    }
+                    continue;
+                }
+                event = logind::next_event(session_events) => {
+                    match event {
+                        Some(event) => {
+                            if let Err(err) = handle_session_event(&connection, options, &state, event).await {
+                                error!("Failed to handle a change of the session: {}", err);
+                            }
+                        }
+                        // Watching the session failed, which has been logged already
+                        None => *session_events = None,
+                    }
+                    continue;
+                }
+                _ = state.config_reloaded.notified() => {
+                    // The mediaplayers to display may have changed along with the format, so look for them again
+                    if let Err(err) = output_current(&connection, options, &state, &mut mediaplayer_busname, &pinned_busname).await {
+                        error!("Failed to output with the new config: {}", err);
+                    }
+                    continue;
+                }
+                _ = state.art.downloaded() => {
+                    // The art of what is displayed may be among what was downloaded
+                    if let Err(err) = output_current(&connection, options, &state, &mut mediaplayer_busname, &pinned_busname).await {
+                        error!("Failed to output with the downloaded art: {}", err);
+                    }
+                    continue;
+                }
+                in_use = audio::next_change(microphone_changes) => {
+                    let result = match in_use {
+                        Some(true) => pause_playing(&connection, options, &state, &state.microphone_paused, "microphone is in use").await,
+                        Some(false) => resume_paused(&connection, &state.microphone_paused, true, "microphone is no longer in use").await,
+                        // Watching the microphone failed, which has been logged already
+                        None => {
+                            *microphone_changes = None;
+                            Ok(())
+                        }
+                    };
+                    if let Err(err) = result {
+                        error!("Failed to toggle playback for the microphone: {}", err);
+                    }
+                    continue;
+                }
+            }
+        };
+
+        // A message that can't be received is skipped, only the end of the stream stops the loop
+        let mut msg = match msg {
+            Some(Ok(msg)) => msg,
+            Some(Err(err)) => {
+                error!("Failed to receive message: {}", err);
+                continue;
+            }
+            None => break,
+        };
+        state.clocks.observe(&msg);
+
+        // Mediaplayers often send several messages at once, so only the last one from the same sender within
+        // a short window is handled. Missing properties are fetched anyway, but a change of state must not be lost.
+        let mut changed_state = changes_state(&msg);
+        let burst = tokio::time::sleep(BURST_WINDOW);
+        tokio::pin!(burst);
+        loop {
+            tokio::select! {
+                _ = &mut burst => break,
+                next = property_stream.next() => match next {
+                    Some(Ok(next)) if next.header().sender() == msg.header().sender() => {
+                        state.clocks.observe(&next);
+                        changed_state |= changes_state(&next);
+                        msg = next;
+                    }
+                    Some(Ok(next)) => {
+                        pending = Some(next);
+                        break;
+                    }
+                    _ => break,
+                },
+            }
+        }
+
+        // Every mediaplayer is output anyway, so there is no need to figure out which one sent the message
+        if options.all_players {
+            if let Err(err) = output_all(&connection, options, &state).await {
+                error!("Failed to output the mediaplayers: {}", err);
+            }
+            continue;
+        }
+
+        // A pinned mediaplayer is displayed as long as it is running
+        if let Some(pinned) = &pinned_busname {
+            if name_owner(&connection, &state, pinned).await.is_some() {
+                pinned.clone_into(&mut mediaplayer_busname);
+            } else {
+                pinned_busname = None;
+            }
+        }
+
+        // If the user defined mediaplayers we try to get the first match in order of priority, but if there is none we skip
+        if pinned_busname.is_none() && !options.mediaplayers().is_empty() && !options.follow {
+            match get_first_match(&dbus_proxy, &state, options).await {
+                Ok(Some(matching_busname)) => {
+                    // We update the mediaplayer with the match
+                    mediaplayer_busname = matching_busname.to_string();
+                }
+                _ => {
+                    // Skip if no match
+                    debug!("Skipping a message, since no matching mediaplayer is running");
+                    continue;
+                }
+            }
+        }
+
+        // Start unpacking the properties from the message, skipping any malformed message
+        let Some(properties) = PropertiesChanged::from_message(msg) else {
+            warn!("Received a message that is not a change of properties");
+            continue;
+        };
+
+        let changed = match properties.args() {
+            Ok(changed) => changed,
+            Err(err) => {
+                error!("Failed to unpack changed properties: {}", err);
+                continue;
+            }
+        };
+
+        // Get the sender busname of the message so that we can check the unique ID
+        let Some(sender) = properties
+            .message()
+            .header()
+            .sender()
+            .map(|sender| sender.to_owned())
+        else {
+            warn!("Received a message without a sender");
+            continue;
+        };
+
+        let sender_busname = BusName::from(sender).to_string();
+
+        debug!(
+            sender = %sender_busname,
+            event = "received",
+            "Received changed properties {:?} from {}",
+            changed.changed_properties().keys().collect::<Vec<_>>(),
+            sender_busname
+        );
+
+        // Don't react at all to mediaplayers that the user wants to ignore
+        match sender_matches(&dbus_proxy, &state, &options.ignore(), &sender_busname).await {
+            Ok(false) => {}
+            Ok(true) => {
+                debug!(sender = %sender_busname, event = "skipped", "Skipping {}, which is ignored", sender_busname);
+                continue;
+            }
+            Err(err) => {
+                error!("Failed to match the sender of a message: {}", err);
+                continue;
+            }
+        }
+
+        // Mirrors of a mediaplayer would show the same track twice, and fight the mediaplayer over autotoggle
+        if !options.keep_mirrors
+            && is_mirror(&connection, &state, &dbus_proxy, &sender_busname).await
+        {
+            debug!(sender = %sender_busname, event = "skipped", "Skipping {}, which mirrors another mediaplayer", sender_busname);
+            continue;
+        }
+
+        // When following we only display the most recently active mediaplayer among the ones we're interested in
+        if pinned_busname.is_none() && options.follow {
+            if !options.mediaplayers().is_empty() {
+                match sender_matches(
+                    &dbus_proxy,
+                    &state,
+                    &options.mediaplayers(),
+                    &sender_busname,
+                )
+                .await
+                {
+                    Ok(true) => {}
+                    Ok(false) => {
+                        debug!(
+                            sender = %sender_busname,
+                            event = "skipped",
+                            "Skipping {}, which is not a mediaplayer to follow",
+                            sender_busname
+                        );
+                        continue;
+                    }
+                    Err(err) => {
+                        error!("Failed to match the sender of a message: {}", err);
+                        continue;
+                    }
+                }
+            }
+
+            if !follow_sender(&state, &sender_busname, changed_state) {
+                debug!(
+                    sender = %sender_busname,
+                    event = "skipped",
+                    "Skipping {}, which is not the most recently active",
+                    sender_busname
+                );
+                continue;
+            }
+
+            sender_busname.clone_into(&mut mediaplayer_busname);
+        } else if pinned_busname.is_none() && options.mediaplayers().is_empty() {
+            // Check if we should listen to all mediaplayers. If so we modify the mediaplayer_bus to whatever is incoming
+            // and proceed to unpacking the contents
+            sender_busname.clone_into(&mut mediaplayer_busname);
+
+            // Remember the sender, so that the output can be cleared when it closes
+            *state.last_sender.lock().expect("Last sender lock poisoned") =
+                Some(sender_busname.clone());
+        } else {
+            // Getting the name owner errors if our mediaplayer is not open...
+            if let Some(mediaplayer_id) =
+                name_owner(&connection, &state, &mediaplayer_busname).await
+            {
+                // If the sender is not a mediaplayer we're after, skip it
+                if sender_busname != mediaplayer_id {
+                    // But first check if we should toggle the playback status
+                    if options.autotoggle
+                        && !autotoggle_ignored(&dbus_proxy, &state, options, &sender_busname).await
+                    {
+                        if let Err(err) = toggle_against(
+                            &connection,
+                            options,
+                            &state,
+                            changed.changed_properties(),
+                            &sender_busname,
+                            &mediaplayer_busname,
+                        )
+                        .await
+                        {
+                            error!(event = "autotoggle", "Failed to toggle playback: {}", err);
+                        }
+                    }
+                    // Since this is not a mediaplayer we care about, just go next and don't unpack any contents
+                    debug!(
+                        sender = %sender_busname,
+                        event = "skipped",
+                        "Skipping {}, which is not {}",
+                        sender_busname, mediaplayer_busname
+                    );
+                    continue;
+                }
+            } else {
+                // ...so in the case that we fail getting the ID of our mediaplayer we skip
+                debug!(
+                    sender = %sender_busname,
+                    event = "skipped",
+                    "Skipping {}, since {} is not running",
+                    sender_busname, mediaplayer_busname
+                );
+                continue;
+            }
+        }
+
+        // Now parse the arguments and finally send the media output to Waybar
+        match parse_msg_args(
+            &connection,
+            options,
+            &state,
+            changed.changed_properties(),
+            &mediaplayer_busname,
+        )
+        .await
+        {
+            Ok(media) => {
+                // Playing again means the user took over, so autotoggle has nothing to resume
+                if media.playbackstatus.as_deref() == Some("Playing") {
+                    forget_autopause(&state, &mediaplayer_busname);
+
+                    if options.pause_others && changed_state {
+                        if let Err(err) = pause_others(
+                            &connection,
+                            &dbus_proxy,
+                            options,
+                            &state,
+                            &mediaplayer_busname,
+                        )
+                        .await
+                        {
+                            error!("Failed to pause the other mediaplayers: {}", err);
+                        }
+                    }
+                }
+                output(&connection, options, &state, Some(&media)).await
+            }
+            Err(err) => error!(
+                player = %mediaplayer_busname,
+                event = "media",
+                "Failed to get the media of {}: {}",
+                mediaplayer_busname, err
+            ),
+        }
+    }
+    Ok(())
+}
+/// Start a message stream receiving info about change of name owners, e.g. mediaplayers closing
+pub async fn name_owner_changed_stream(
+    connection: Connection,
+    options: &Arguments,
+    state: Arc<State>,
+) -> Result<(), BoxedError> {
+    let dbus_proxy = DBusProxy::new(&connection).await?;
+
+    // Define a rule to catch properties changed
+    let mut name_owner_changed_stream = dbus_proxy.receive_name_owner_changed().await?;
+
+    while let Some(ownership_change) = name_owner_changed_stream.next().await {
+        // Unpack the changes in name owner, skipping any malformed message
+        let change = match ownership_change.args() {
+            Ok(change) => change,
+            Err(err) => {
+                error!("Failed to unpack the name owner change: {}", err);
+                continue;
+            }
+        };
+
+        debug!(
+            player = %change.name(),
+            event = "owner_changed",
+            "Owner of {} changed from {:?} to {:?}",
+            change.name(),
+            change.old_owner().as_ref().map(|owner| owner.as_str()),
+            change.new_owner().as_ref().map(|owner| owner.as_str())
+        );
+
+        // Only care about the human readable names that contains MPRIS players
+        if let BusName::WellKnown(bus_name) = change.name() {
+            // Whatever happened, the cached owner is no longer to be trusted
+            state
+                .name_owners
+                .lock()
+                .expect("Name owners lock poisoned")
+                .remove(bus_name.as_str());
+
+            if bus_name.contains("org.mpris.MediaPlayer2.") {
+                let name = bus_name.trim_start_matches("org.mpris.MediaPlayer2.");
+
+                // Both owners now own other names than before
+                forget_names(
+                    &state,
+                    &[
+                        change.old_owner().as_ref().map(|owner| owner.as_str()),
+                        change.new_owner().as_ref().map(|owner| owner.as_str()),
+                    ],
+                );
+
+                // Don't react at all to mediaplayers that the user wants to ignore
+                if is_ignored(options, name) {
+                    continue;
+                }
+
+                // The list of mediaplayers changed, which is all there is to it when outputting all of them
+                if options.all_players {
+                    if let Err(err) = output_all(&connection, options, &state).await {
+                        error!("Failed to output the mediaplayers: {}", err);
+                    }
+                    continue;
+                }
+
+                // A closing mediaplayer no longer interrupts ours, and ours closing has nothing left to resume
+                if options.autotoggle {
+                    if let (Some(old_owner), None) =
+                        (change.old_owner().as_ref(), change.new_owner().as_ref())
+                    {
+                        forget_autopause(&state, bus_name.as_str());
+                        if let Err(err) =
+                            resume(&connection, options, &state, old_owner.as_str()).await
+                        {
+                            error!(event = "autotoggle", "Failed to toggle playback: {}", err);
+                        }
+                    }
+                }
+
+                // When following, a closing mediaplayer hands over the output to the previously active one
+                if options.follow {
+                    if let (Some(old_owner), None) =
+                        (change.old_owner().as_ref(), change.new_owner().as_ref())
+                    {
+                        match unfollow(&state, old_owner.as_str()) {
+                            Some(Some(next_busname)) => {
+                                if let Err(err) =
+                                    refresh(&connection, options, &state, &next_busname).await
+                                {
+                                    error!("Failed to update the output: {}", err);
+                                }
+                            }
+                            // Clear the output if there is nothing left to follow
+                            Some(None) => output(&connection, options, &state, None).await,
+                            None => {}
+                        }
+                    }
+                    continue;
+                }
+
+                // Check if the mediaplayer matches any of the mediaplayers defined by the user,
+                // where a closed one can only be matched by identity if it was asked before
+                let owner = change
+                    .new_owner()
+                    .as_ref()
+                    .or(change.old_owner().as_ref())
+                    .map(|owner| owner.to_string())
+                    .unwrap_or_default();
+                let matched_player =
+                    matches_any(&connection, &state, &options.mediaplayers(), name, &owner).await;
+
+                // When listening to all mediaplayers, the output is cleared once the one that produced it closes
+                if options.mediaplayers().is_empty() {
+                    if let (Some(old_owner), None) =
+                        (change.old_owner().as_ref(), change.new_owner().as_ref())
+                    {
+                        let closed = {
+                            let mut last_sender =
+                                state.last_sender.lock().expect("Last sender lock poisoned");
+                            let closed = last_sender.as_deref() == Some(old_owner.as_str());
+                            if closed {
+                                *last_sender = None;
+                            }
+                            closed
+                        };
+
+                        if closed {
+                            output(&connection, options, &state, None).await;
+                        }
+                    }
+                    continue;
+                }
+
+                // A typical message when a mediaplayer closes contains info about the old owner
+                // but there is no no new owner, and it should match a player we're interested in.
+                if change.old_owner().is_some() && change.new_owner().is_none() && matched_player {
+                    // Fall back to the next running mediaplayer in order of priority, if any
+                    if let Ok(Some(fallback_busname)) =
+                        get_first_match(&dbus_proxy, &state, options).await
+                    {
+                        if let Err(err) =
+                            refresh(&connection, options, &state, &fallback_busname).await
+                        {
+                            error!("Failed to update the output: {}", err);
+                        }
+                    } else {
+                        // Clear the output if there is nothing to fall back to
+                        output(&connection, options, &state, None).await;
+                    }
+                }
+
+                // Firefox sometimes appear as a new name owner, with content playing (usually a stream) but does not
+                // send any message about it. Therefore we check all non matching players playback status as they appear
+                // and toggle playback accordingly.
+                if change.old_owner().is_none()
+                    && change.new_owner().is_some()
+                    && !matched_player
+                    && options.autotoggle
+                    && !autotoggle_ignored(&dbus_proxy, &state, options, &owner).await
+                    && (options.keep_mirrors
+                        || !is_mirror(&connection, &state, &dbus_proxy, &owner).await)
+                {
+                    // Figure out the correct busname to call
+                    let Ok(mediaplayer_busname) =
+                        get_first_match(&dbus_proxy, &state, options).await
+                    else {
+                        // This can fail, in that case we skip
+                        continue;
+                    };
+
+                    // Then send a command to pause our mediaplayer. Any other status we just ignore.
+                    if let (Some(mediaplayer_busname), Some(new_owner)) =
+                        (mediaplayer_busname, change.new_owner().as_ref())
+                    {
+                        if let Err(err) = pause_if_playing(
+                            &connection,
+                            options,
+                            &state,
+                            new_owner.as_str(),
+                            &mediaplayer_busname,
+                        )
+                        .await
+                        {
+                            error!(event = "autotoggle", "Failed to toggle playback: {}", err);
+                        }
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
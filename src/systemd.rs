@@ -0,0 +1,40 @@
+use crate::BoxedError;
+use std::os::unix::net::UnixDatagram;
+use std::time::Duration;
+
+/// Tell systemd about the state of the service, if it is running as one with Type=notify
+pub fn notify(state: &str) -> Result<(), BoxedError> {
+    let Some(path) = std::env::var_os("NOTIFY_SOCKET") else {
+        return Ok(());
+    };
+
+    let socket = UnixDatagram::unbound()?;
+
+    // A leading @ means the socket is in the abstract namespace
+    match path.to_str().and_then(|path| path.strip_prefix('@')) {
+        Some(name) => {
+            use std::os::linux::net::SocketAddrExt;
+            let address = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+            socket.send_to_addr(state.as_bytes(), &address)?;
+        }
+        None => {
+            socket.send_to(state.as_bytes(), path)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// How often to ping the watchdog, which is half of what systemd allows to be safe
+pub fn watchdog_interval() -> Option<Duration> {
+    let microseconds: u64 = std::env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+
+    // The watchdog is meant for another process if the pid is not ours
+    if let Ok(pid) = std::env::var("WATCHDOG_PID") {
+        if pid.parse() != Ok(std::process::id()) {
+            return None;
+        }
+    }
+
+    (microseconds > 0).then(|| Duration::from_micros(microseconds / 2))
+}
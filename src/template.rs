@@ -0,0 +1,400 @@
+use crate::media;
+
+/// The tags that can be used in a template
+pub const TAGS: [&str; 18] = [
+    "artist",
+    "artists",
+    "title",
+    "status",
+    "statuslabel",
+    "position",
+    "length",
+    "progress",
+    "album",
+    "albumartist",
+    "rating",
+    "art",
+    "url",
+    "domain",
+    "shuffle",
+    "loop",
+    "volume",
+    "player",
+];
+
+/// Changes applied to the value of a tag, such as {{title|upper}}
+#[derive(Clone)]
+enum Modifier {
+    Upper,
+    Lower,
+    Truncate(usize),
+    Default(String),
+}
+
+impl Modifier {
+    fn parse(modifier: &str) -> Result<Self, String> {
+        match modifier.split_once(':') {
+            None if modifier == "upper" => Ok(Modifier::Upper),
+            None if modifier == "lower" => Ok(Modifier::Lower),
+            Some(("truncate", length)) => length
+                .parse()
+                .map(Modifier::Truncate)
+                .map_err(|_| format!("invalid length '{}' for truncate", length)),
+            Some(("default", text)) => Ok(Modifier::Default(text.to_string())),
+            _ => Err(format!(
+                "unknown modifier '{}', supported modifiers are upper, lower, truncate:N and default:TEXT",
+                modifier
+            )),
+        }
+    }
+
+    fn apply(&self, value: String) -> String {
+        match self {
+            Modifier::Upper => value.to_uppercase(),
+            Modifier::Lower => value.to_lowercase(),
+            Modifier::Truncate(length) => media::truncate(value, *length, "…"),
+            Modifier::Default(text) if value.is_empty() => text.clone(),
+            Modifier::Default(_) => value,
+        }
+    }
+}
+
+/// A piece of a template
+#[derive(Clone)]
+enum Node {
+    Text(String),
+    // The first of the tags with a value is used, such as {{artist|albumartist|player}}
+    Tag {
+        names: Vec<String>,
+        modifiers: Vec<Modifier>,
+    },
+    // A section that is only rendered if a tag has a value, or only if it has none
+    If {
+        name: String,
+        negated: bool,
+        then: Vec<Node>,
+        otherwise: Vec<Node>,
+    },
+}
+
+/// A section that has been opened but not yet closed while parsing
+struct Section {
+    name: String,
+    negated: bool,
+    then: Vec<Node>,
+    otherwise: Option<Vec<Node>>,
+}
+
+impl Section {
+    /// The nodes that come next, which are those after {{else}} once it has been seen
+    fn nodes(&mut self) -> &mut Vec<Node> {
+        self.otherwise.as_mut().unwrap_or(&mut self.then)
+    }
+
+    fn keyword(&self) -> &'static str {
+        if self.negated {
+            "unless"
+        } else {
+            "if"
+        }
+    }
+}
+
+/// A user defined template of tags, conditional sections and modifiers, parsed once rather than on every render
+#[derive(Clone)]
+pub struct Template {
+    nodes: Vec<Node>,
+}
+
+/// The widest {{progress:N}} can be, which is already far wider than any bar
+const MAX_PROGRESS_WIDTH: usize = 200;
+
+/// Check if a tag is one of the known tags, a progress bar of some width or any key of the metadata such as {{meta:xesam:genre}}
+fn is_tag(name: &str) -> bool {
+    TAGS.contains(&name)
+        || name
+            .strip_prefix("meta:")
+            .is_some_and(|key| !key.is_empty())
+        || name.strip_prefix("progress:").is_some_and(|width| {
+            width
+                .parse::<usize>()
+                .is_ok_and(|width| (1..=MAX_PROGRESS_WIDTH).contains(&width))
+        })
+}
+
+/// Check that a tag is one that can have a value
+fn known_tag(name: &str) -> Result<String, String> {
+    if is_tag(name) {
+        Ok(name.to_string())
+    } else if let Some(width) = name.strip_prefix("progress:") {
+        Err(format!(
+            "invalid width '{}' of '{{{{progress:N}}}}', expected a number from 1 to {}",
+            width, MAX_PROGRESS_WIDTH
+        ))
+    } else {
+        Err(format!(
+            "unknown tag '{{{{{}}}}}', supported tags are {}, {{{{progress:N}}}} and {{{{meta:KEY}}}}",
+            name,
+            TAGS.iter()
+                .map(|tag| format!("{{{{{}}}}}", tag))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ))
+    }
+}
+
+impl std::str::FromStr for Template {
+    type Err = String;
+
+    fn from_str(template: &str) -> Result<Self, Self::Err> {
+        let mut nodes: Vec<Node> = Vec::new();
+        let mut sections: Vec<Section> = Vec::new();
+        let mut rest = template;
+
+        loop {
+            let (text, tag) = match (rest.find("{{"), rest.find("}}")) {
+                (None, None) => (rest, None),
+                (Some(open), Some(close)) if open < close => {
+                    let tag = &rest[open + 2..close];
+                    if tag.contains("{{") {
+                        return Err(format!("unclosed '{{{{' at '{}'", &rest[open..]));
+                    }
+                    let text = &rest[..open];
+                    rest = &rest[close + 2..];
+                    (text, Some(tag.trim()))
+                }
+                (Some(open), None) => {
+                    return Err(format!("unclosed '{{{{' at '{}'", &rest[open..]))
+                }
+                (_, Some(close)) => {
+                    return Err(format!(
+                        "'}}}}' without a matching '{{{{' in '{}'",
+                        &rest[..close + 2]
+                    ))
+                }
+            };
+
+            let current = match sections.last_mut() {
+                Some(section) => section.nodes(),
+                None => &mut nodes,
+            };
+
+            if !text.is_empty() {
+                current.push(Node::Text(text.to_string()));
+            }
+
+            let Some(tag) = tag else {
+                break;
+            };
+
+            if let Some(name) = tag.strip_prefix("#if ") {
+                sections.push(Section {
+                    name: known_tag(name.trim())?,
+                    negated: false,
+                    then: Vec::new(),
+                    otherwise: None,
+                });
+            } else if let Some(name) = tag.strip_prefix("#unless ") {
+                sections.push(Section {
+                    name: known_tag(name.trim())?,
+                    negated: true,
+                    then: Vec::new(),
+                    otherwise: None,
+                });
+            } else if tag == "else" {
+                match sections.last_mut() {
+                    Some(section) if section.otherwise.is_none() => {
+                        section.otherwise = Some(Vec::new())
+                    }
+                    Some(_) => return Err(String::from("more than one '{{else}}' in a section")),
+                    None => return Err(String::from("'{{else}}' outside of a section")),
+                }
+            } else if let Some(keyword) = tag.strip_prefix('/') {
+                let section = match sections.pop() {
+                    Some(section) if section.keyword() == keyword => section,
+                    Some(section) => {
+                        return Err(format!(
+                            "'{{{{/{}}}}}' closes '{{{{#{} {}}}}}'",
+                            keyword,
+                            section.keyword(),
+                            section.name
+                        ))
+                    }
+                    None => return Err(format!("'{{{{/{}}}}}' without a section", keyword)),
+                };
+                let node = Node::If {
+                    name: section.name,
+                    negated: section.negated,
+                    then: section.then,
+                    otherwise: section.otherwise.unwrap_or_default(),
+                };
+                match sections.last_mut() {
+                    Some(parent) => parent.nodes().push(node),
+                    None => nodes.push(node),
+                }
+            } else {
+                let mut parts = tag.split('|').map(str::trim).peekable();
+                let mut names = vec![known_tag(parts.next().unwrap_or_default())?];
+
+                // Tags to fall back on come first, anything after them is a modifier
+                while let Some(name) = parts.next_if(|part| is_tag(part)) {
+                    names.push(name.to_string());
+                }
+
+                current.push(Node::Tag {
+                    names,
+                    modifiers: parts.map(Modifier::parse).collect::<Result<_, _>>()?,
+                });
+            }
+        }
+
+        match sections.last() {
+            Some(section) => Err(format!(
+                "unclosed '{{{{#{} {}}}}}'",
+                section.keyword(),
+                section.name
+            )),
+            None => Ok(Template { nodes }),
+        }
+    }
+}
+
+impl Template {
+    /// Render the template, with missing values as empty text
+    pub fn render(&self, value: impl Fn(&str) -> Option<String>) -> String {
+        let mut output = String::new();
+        render_nodes(&self.nodes, &value, &mut output);
+        output
+    }
+}
+
+fn render_nodes(nodes: &[Node], value: &dyn Fn(&str) -> Option<String>, output: &mut String) {
+    for node in nodes {
+        match node {
+            Node::Text(text) => output.push_str(text),
+            Node::Tag { names, modifiers } => {
+                let first = names
+                    .iter()
+                    .filter_map(|name| value(name))
+                    .find(|value| !value.trim().is_empty())
+                    .unwrap_or_default();
+                output.push_str(
+                    &modifiers
+                        .iter()
+                        .fold(first, |value, modifier| modifier.apply(value)),
+                );
+            }
+            Node::If {
+                name,
+                negated,
+                then,
+                otherwise,
+            } => {
+                // Blank text counts as missing, since some mediaplayers send that instead of nothing
+                let present = value(name).is_some_and(|value| !value.trim().is_empty());
+                if present != *negated {
+                    render_nodes(then, value, output);
+                } else {
+                    render_nodes(otherwise, value, output);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Render a template with the given tags set, and all others missing
+    fn render(template: &str, values: &[(&str, &str)]) -> String {
+        template
+            .parse::<Template>()
+            .unwrap_or_else(|err| panic!("failed to parse '{}': {}", template, err))
+            .render(|name| {
+                values
+                    .iter()
+                    .find(|(tag, _)| *tag == name)
+                    .map(|(_, value)| value.to_string())
+            })
+    }
+
+    #[test]
+    fn renders_nested_sections() {
+        let template = "{{#if artist}}{{artist}}{{#unless album}} (single){{else}} on {{album}}{{/unless}}{{else}}{{title}}{{/if}}";
+
+        assert_eq!(
+            render(template, &[("artist", "A"), ("album", "B")]),
+            "A on B"
+        );
+        assert_eq!(render(template, &[("artist", "A")]), "A (single)");
+        assert_eq!(render(template, &[("title", "T"), ("album", "B")]), "T");
+    }
+
+    #[test]
+    fn takes_blank_values_as_missing() {
+        assert_eq!(
+            render("{{#if album}}on {{album}}{{/if}}", &[("album", "  ")]),
+            ""
+        );
+        assert_eq!(
+            render("{{#unless album}}no album{{/unless}}", &[("album", "")]),
+            "no album"
+        );
+    }
+
+    #[test]
+    fn falls_back_on_the_first_tag_with_a_value() {
+        let template = "{{artist|albumartist|player|upper}}";
+
+        assert_eq!(
+            render(template, &[("artist", "a"), ("albumartist", "b")]),
+            "A"
+        );
+        assert_eq!(
+            render(template, &[("artist", " "), ("player", "spotify")]),
+            "SPOTIFY"
+        );
+        assert_eq!(render(template, &[]), "");
+        assert_eq!(render("{{album|default:none}}", &[("album", "")]), "none");
+    }
+
+    #[test]
+    fn rejects_progress_bars_too_wide() {
+        assert!("{{progress:200}}".parse::<Template>().is_ok());
+        assert_eq!(
+            "{{progress:99999999999}}"
+                .parse::<Template>()
+                .err()
+                .as_deref(),
+            Some(
+                "invalid width '99999999999' of '{{progress:N}}', expected a number from 1 to 200"
+            )
+        );
+    }
+
+    #[test]
+    fn rejects_unbalanced_tags() {
+        for template in [
+            "{{#if artist}}open",
+            "closed{{/if}}",
+            "{{#if artist}}mixed{{/unless}}",
+            "{{#if artist}}{{#unless album}}inner{{/if}}{{/unless}}",
+            "{{else}}",
+            "{{#if artist}}a{{else}}b{{else}}c{{/if}}",
+            "{{artist",
+            "artist}}",
+            "{{artist {{title}}",
+            "{{unknown}}",
+            "{{#if unknown}}{{/if}}",
+            "{{title|shout}}",
+            "{{progress:0}}",
+        ] {
+            assert!(
+                template.parse::<Template>().is_err(),
+                "'{}' should not parse",
+                template
+            );
+        }
+    }
+}
@@ -0,0 +1,57 @@
+use crate::media::Media;
+
+/// What we know about a track, used to tell a new track apart from corrected metadata of the same one
+pub struct Track {
+    trackid: Option<String>,
+    artist: Option<String>,
+    title: Option<String>,
+    length: Option<i64>,
+    position: Option<i64>,
+}
+
+impl Track {
+    /// Take the identifying parts of the media
+    pub fn from_media(media: &Media) -> Self {
+        Track {
+            trackid: media.metadata.trackid.clone(),
+            artist: media.metadata.artist.clone(),
+            title: media.metadata.title.clone(),
+            length: media.metadata.length,
+            position: media.position,
+        }
+    }
+
+    /// Check if this is a different track than the previous one, or the same track played again
+    pub fn is_new(&self, previous: &Track) -> bool {
+        // Mediaplayers with a trackid make it easy, but some use the same trackid for everything
+        if differs(&self.trackid, &previous.trackid) {
+            return true;
+        }
+
+        // A changed artist or title means a new track, but not when a missing value is filled in
+        if differs(&self.artist, &previous.artist) || differs(&self.title, &previous.title) {
+            return true;
+        }
+
+        // Another length means another version of the track, allowing for some rounding
+        if let (Some(length), Some(previous_length)) = (self.length, previous.length) {
+            if (length - previous_length).abs() > 1_000_000 {
+                return true;
+            }
+        }
+
+        // A position that jumps back to the start means that the track is played again
+        if let (Some(position), Some(previous_position)) = (self.position, previous.position) {
+            if position < 2_000_000 && previous_position > 10_000_000 {
+                return true;
+            }
+        }
+
+        false
+    }
+}
+
+/// Check if two values are both known and different
+fn differs<T: PartialEq>(value: &Option<T>, previous: &Option<T>) -> bool {
+    matches!((value, previous), (Some(value), Some(previous)) if value != previous)
+}
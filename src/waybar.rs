@@ -0,0 +1,26 @@
+use std::process::Stdio;
+use tokio::process::Command;
+use tokio::sync::watch;
+use tracing::{debug, error};
+
+/// Send Waybar SIGRTMIN+NUMBER whenever the output changes, so that modules with `"signal": NUMBER` run their exec again
+pub async fn signal(number: u8, mut changes: watch::Receiver<Option<String>>) {
+    while changes.changed().await.is_ok() {
+        let status = Command::new("pkill")
+            .arg(format!("-RTMIN+{}", number))
+            .args(["-x", "waybar"])
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .await;
+
+        // pkill exits with 1 when nothing matched, which only means that Waybar is not running right now
+        match status {
+            Ok(status) if status.success() => {}
+            Ok(status) if status.code() == Some(1) => debug!("Waybar is not running to signal"),
+            Ok(status) => error!("Failed to signal Waybar, pkill exited with {}", status),
+            Err(err) => error!("Failed to run pkill to signal Waybar: {}", err),
+        }
+    }
+}
@@ -0,0 +1,24 @@
+use std::path::PathBuf;
+
+/// A base directory from its XDG variable, or else its default in the home directory
+fn base_dir(variable: &str, default: &str) -> Option<PathBuf> {
+    match std::env::var_os(variable) {
+        Some(dir) if !dir.is_empty() => Some(PathBuf::from(dir)),
+        _ => Some(PathBuf::from(std::env::var_os("HOME")?).join(default)),
+    }
+}
+
+/// Where to keep files that can be made again, such as downloaded art
+pub fn cache_home() -> Option<PathBuf> {
+    base_dir("XDG_CACHE_HOME", ".cache")
+}
+
+/// Where the user keeps their configuration
+pub fn config_home() -> Option<PathBuf> {
+    base_dir("XDG_CONFIG_HOME", ".config")
+}
+
+/// Where to keep data that is worth keeping, such as the history of plays
+pub fn data_home() -> Option<PathBuf> {
+    base_dir("XDG_DATA_HOME", ".local/share")
+}
@@ -0,0 +1,361 @@
+//! A private session bus with fake mediaplayers on it, to run lizzy against
+
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader};
+use std::path::PathBuf;
+use std::process::{Child, ChildStdout, Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use zbus::object_server::SignalEmitter;
+use zbus::zvariant::{OwnedValue, Str, Value};
+use zbus::{connection, interface, Connection};
+
+const PATH: &str = "/org/mpris/MediaPlayer2";
+
+/// How long to wait for lizzy to print something before a test fails
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A dbus-daemon of its own for each test, so that tests don't see each other or the real session
+pub struct Bus {
+    daemon: Child,
+    dir: PathBuf,
+    pub address: String,
+}
+
+impl Bus {
+    /// Start a bus, or none if dbus-daemon is not installed, in which case the test is skipped
+    pub fn start() -> Option<Bus> {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+
+        let dir = std::env::temp_dir().join(format!(
+            "lizzy-test-{}-{}",
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::create_dir_all(&dir).expect("Failed to create a directory for the bus");
+        let address = format!("unix:path={}", dir.join("bus").display());
+
+        let mut daemon = match Command::new("dbus-daemon")
+            .args(["--session", "--nofork", "--print-address"])
+            .arg(format!("--address={}", address))
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+        {
+            Ok(daemon) => daemon,
+            Err(_) => {
+                eprintln!("dbus-daemon is not installed, skipping");
+                return None;
+            }
+        };
+
+        // The address is printed once the bus is ready
+        let mut line = String::new();
+        BufReader::new(daemon.stdout.take().expect("Bus has stdout"))
+            .read_line(&mut line)
+            .expect("Failed to wait for the bus");
+
+        Some(Bus {
+            daemon,
+            dir,
+            address,
+        })
+    }
+
+    /// Run lizzy with the given options on this bus, with plain output to read from
+    pub fn lizzy(&self, args: &[&str]) -> Lizzy {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_lizzy"))
+            .args(["--output", "plain"])
+            .args(args)
+            .env("DBUS_SESSION_BUS_ADDRESS", &self.address)
+            .env("XDG_CONFIG_HOME", &self.dir)
+            .stdout(Stdio::piped())
+            .spawn()
+            .expect("Failed to start lizzy");
+
+        // Lines are read on a thread of their own so that waiting for one can time out
+        let (sender, lines) = mpsc::channel();
+        let stdout: ChildStdout = child.stdout.take().expect("lizzy has stdout");
+        std::thread::spawn(move || {
+            for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+                if sender.send(line).is_err() {
+                    return;
+                }
+            }
+        });
+
+        Lizzy { child, lines }
+    }
+}
+
+impl Drop for Bus {
+    fn drop(&mut self) {
+        let _ = self.daemon.kill();
+        let _ = self.daemon.wait();
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// A running lizzy whose output is checked line by line
+pub struct Lizzy {
+    child: Child,
+    lines: mpsc::Receiver<String>,
+}
+
+impl Lizzy {
+    /// The next line of output, failing the test if none comes in time
+    pub fn next_line(&self) -> String {
+        self.lines
+            .recv_timeout(TIMEOUT)
+            .expect("lizzy printed nothing in time")
+    }
+
+    /// Skip lines until one is the expected line, failing the test if it never comes
+    pub fn expect_line(&self, expected: &str) {
+        let mut seen = Vec::new();
+        while let Ok(line) = self.lines.recv_timeout(TIMEOUT) {
+            if line == expected {
+                return;
+            }
+            seen.push(line);
+        }
+        panic!("lizzy never printed {:?}, only {:?}", expected, seen);
+    }
+}
+
+impl Drop for Lizzy {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// The root interface, which lizzy only needs for the identity
+struct Root {
+    identity: String,
+}
+
+#[interface(name = "org.mpris.MediaPlayer2")]
+impl Root {
+    #[zbus(property)]
+    fn identity(&self) -> String {
+        self.identity.clone()
+    }
+
+    #[zbus(property)]
+    fn desktop_entry(&self) -> String {
+        self.identity.to_lowercase()
+    }
+
+    #[zbus(property)]
+    fn can_raise(&self) -> bool {
+        false
+    }
+
+    #[zbus(property)]
+    fn can_quit(&self) -> bool {
+        false
+    }
+}
+
+/// The player interface, remembering which methods were called on it
+struct Player {
+    status: String,
+    metadata: HashMap<String, OwnedValue>,
+    calls: Arc<Mutex<Vec<String>>>,
+}
+
+impl Player {
+    async fn set_status(&mut self, status: &str, emitter: &SignalEmitter<'_>) {
+        self.status = status.to_string();
+        self.playback_status_changed(emitter)
+            .await
+            .expect("Failed to signal the playback status");
+    }
+}
+
+#[interface(name = "org.mpris.MediaPlayer2.Player")]
+impl Player {
+    async fn play(&mut self, #[zbus(signal_emitter)] emitter: SignalEmitter<'_>) {
+        self.calls.lock().unwrap().push(String::from("Play"));
+        self.set_status("Playing", &emitter).await;
+    }
+
+    async fn pause(&mut self, #[zbus(signal_emitter)] emitter: SignalEmitter<'_>) {
+        self.calls.lock().unwrap().push(String::from("Pause"));
+        self.set_status("Paused", &emitter).await;
+    }
+
+    async fn play_pause(&mut self, #[zbus(signal_emitter)] emitter: SignalEmitter<'_>) {
+        self.calls.lock().unwrap().push(String::from("PlayPause"));
+        let status = if self.status == "Playing" {
+            "Paused"
+        } else {
+            "Playing"
+        };
+        self.set_status(status, &emitter).await;
+    }
+
+    #[zbus(property)]
+    fn playback_status(&self) -> String {
+        self.status.clone()
+    }
+
+    #[zbus(property)]
+    fn metadata(&self) -> HashMap<String, OwnedValue> {
+        self.metadata
+            .iter()
+            .map(|(key, value)| (key.clone(), value.try_clone().expect("Metadata is plain")))
+            .collect()
+    }
+
+    #[zbus(property)]
+    fn position(&self) -> i64 {
+        0
+    }
+
+    #[zbus(property)]
+    fn can_control(&self) -> bool {
+        true
+    }
+
+    #[zbus(property)]
+    fn can_play(&self) -> bool {
+        true
+    }
+
+    #[zbus(property)]
+    fn can_pause(&self) -> bool {
+        true
+    }
+}
+
+/// The metadata of a track, as mediaplayers send it
+fn metadata(artist: &str, title: &str) -> HashMap<String, OwnedValue> {
+    let value = |value: Value<'_>| OwnedValue::try_from(value).expect("Metadata is plain");
+    HashMap::from([
+        (
+            String::from("mpris:trackid"),
+            value(Value::from(
+                zbus::zvariant::ObjectPath::try_from(format!(
+                    "/track/{}",
+                    title.replace(|c: char| !c.is_ascii_alphanumeric(), "_")
+                ))
+                .expect("Track id is a valid path"),
+            )),
+        ),
+        (
+            String::from("xesam:title"),
+            value(Value::from(Str::from(title.to_string()))),
+        ),
+        (
+            String::from("xesam:artist"),
+            value(Value::from(vec![artist.to_string()])),
+        ),
+        (
+            String::from("mpris:length"),
+            value(Value::from(200_000_000i64)),
+        ),
+    ])
+}
+
+/// A fake mediaplayer on the bus, which goes away when it is dropped
+pub struct FakePlayer {
+    connection: Connection,
+    pub calls: Arc<Mutex<Vec<String>>>,
+}
+
+impl FakePlayer {
+    /// Put a mediaplayer on the bus as org.mpris.MediaPlayer2.NAME, playing a track
+    pub async fn start(bus: &Bus, name: &str, artist: &str, title: &str) -> FakePlayer {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let connection = connection::Builder::address(bus.address.as_str())
+            .expect("Bus address is valid")
+            .name(format!("org.mpris.MediaPlayer2.{}", name))
+            .expect("Player name is valid")
+            .serve_at(
+                PATH,
+                Root {
+                    identity: name.to_string(),
+                },
+            )
+            .expect("Failed to serve the root interface")
+            .serve_at(
+                PATH,
+                Player {
+                    status: String::from("Playing"),
+                    metadata: metadata(artist, title),
+                    calls: calls.clone(),
+                },
+            )
+            .expect("Failed to serve the player interface")
+            .build()
+            .await
+            .expect("Failed to put the mediaplayer on the bus");
+
+        FakePlayer { connection, calls }
+    }
+
+    async fn update(
+        &self,
+        change: impl FnOnce(&mut Player),
+    ) -> zbus::object_server::InterfaceRef<Player> {
+        let interface = self
+            .connection
+            .object_server()
+            .interface::<_, Player>(PATH)
+            .await
+            .expect("Player interface is served");
+        change(&mut *interface.get_mut().await);
+        interface
+    }
+
+    /// Change the track, as happens when the mediaplayer moves on to the next one
+    pub async fn set_track(&self, artist: &str, title: &str) {
+        let interface = self
+            .update(|player| player.metadata = metadata(artist, title))
+            .await;
+        interface
+            .get()
+            .await
+            .metadata_changed(interface.signal_emitter())
+            .await
+            .expect("Failed to signal the metadata");
+    }
+
+    /// Change the playback status, as happens when the user presses play or pause in the mediaplayer
+    pub async fn set_status(&self, status: &str) {
+        let interface = self.update(|_| {}).await;
+        interface
+            .get_mut()
+            .await
+            .set_status(status, interface.signal_emitter())
+            .await;
+    }
+
+    /// Leave the bus, as happens when the mediaplayer is closed
+    pub async fn quit(self) {
+        self.connection
+            .close()
+            .await
+            .expect("Failed to leave the bus");
+    }
+
+    /// The methods lizzy called, such as Pause and Play
+    pub fn calls(&self) -> Vec<String> {
+        self.calls.lock().unwrap().clone()
+    }
+}
+
+/// Wait until a condition holds, failing the test if it never does
+pub async fn eventually(what: &str, condition: impl Fn() -> bool) {
+    for _ in 0..50 {
+        if condition() {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("Timed out waiting for {}", what);
+}
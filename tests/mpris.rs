@@ -0,0 +1,111 @@
+//! lizzy from start to end, against fake mediaplayers on a private session bus
+
+mod common;
+
+use common::{eventually, Bus, FakePlayer};
+
+// The fake mediaplayers answer lizzy on the runtime, while the tests block on reading the output of lizzy,
+// so every test needs a runtime with more than one thread
+
+#[tokio::test(flavor = "multi_thread")]
+async fn prints_the_new_track() {
+    let Some(bus) = Bus::start() else {
+        return;
+    };
+    let player = FakePlayer::start(&bus, "music", "Artist", "First").await;
+    let lizzy = bus.lizzy(&["--mediaplayer", "music"]);
+
+    // lizzy prints on changes, so wait for it to be listening before changing anything
+    tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+    player.set_track("Artist", "Second").await;
+    lizzy.expect_line("Artist - Second");
+
+    player.set_track("Other", "Third").await;
+    lizzy.expect_line("Other - Third");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn shows_the_paused_format() {
+    let Some(bus) = Bus::start() else {
+        return;
+    };
+    let player = FakePlayer::start(&bus, "music", "Artist", "Song").await;
+    let lizzy = bus.lizzy(&[
+        "--mediaplayer",
+        "music",
+        "--format-paused",
+        "paused: {{title}}",
+    ]);
+
+    tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+    player.set_status("Paused").await;
+    lizzy.expect_line("paused: Song");
+
+    player.set_status("Playing").await;
+    lizzy.expect_line("Artist - Song");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn shows_the_idle_text_when_the_mediaplayer_exits() {
+    let Some(bus) = Bus::start() else {
+        return;
+    };
+    let player = FakePlayer::start(&bus, "music", "Artist", "Song").await;
+    let lizzy = bus.lizzy(&["--mediaplayer", "music", "--idle-text", "idle"]);
+    assert_eq!(lizzy.next_line(), "idle");
+
+    tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+    player.set_track("Artist", "Another").await;
+    lizzy.expect_line("Artist - Another");
+
+    player.quit().await;
+    lizzy.expect_line("idle");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn autotoggle_pauses_and_resumes() {
+    let Some(bus) = Bus::start() else {
+        return;
+    };
+    let music = FakePlayer::start(&bus, "music", "Artist", "Song").await;
+    let video = FakePlayer::start(&bus, "video", "Channel", "Video").await;
+    video.set_status("Paused").await;
+    let _lizzy = bus.lizzy(&[
+        "--mediaplayer",
+        "music",
+        "--autotoggle",
+        "--autotoggle-cooldown",
+        "0",
+    ]);
+
+    // Another mediaplayer that starts playing pauses the music, which resumes once it stops again
+    tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+    video.set_status("Playing").await;
+    eventually("the music to be paused", || music.calls() == ["Pause"]).await;
+
+    video.set_status("Paused").await;
+    eventually("the music to be resumed", || {
+        music.calls() == ["Pause", "Play"]
+    })
+    .await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn autotoggle_leaves_music_paused_by_the_user() {
+    let Some(bus) = Bus::start() else {
+        return;
+    };
+    let music = FakePlayer::start(&bus, "music", "Artist", "Song").await;
+    let video = FakePlayer::start(&bus, "video", "Channel", "Video").await;
+    video.set_status("Paused").await;
+    music.set_status("Paused").await;
+    let _lizzy = bus.lizzy(&["--mediaplayer", "music", "--autotoggle"]);
+
+    // Nothing is paused by lizzy, so there is nothing for it to resume either
+    tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+    video.set_status("Playing").await;
+    tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+    video.set_status("Paused").await;
+    tokio::time::sleep(std::time::Duration::from_millis(1000)).await;
+    assert!(music.calls().is_empty(), "lizzy called {:?}", music.calls());
+}